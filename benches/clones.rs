@@ -0,0 +1,50 @@
+//! Times passing large collections through a function, which copies the
+//! value for each call. Run with `cargo bench --bench clones`.
+
+use std::{io, time::Instant};
+
+use zeal::{parser::Value, Compiler};
+
+const SIZE: i32 = 100_000;
+const CALLS: usize = 2_000;
+
+fn main() {
+    let elements = || (0..SIZE).map(Value::Int).collect::<Vec<_>>();
+    let values = [
+        ("string", Value::from("a".repeat(SIZE as usize))),
+        ("list", Value::List(elements().into())),
+        ("tuple", Value::Tuple(elements().into())),
+        ("set", Value::Set(elements().into())),
+        (
+            "dict",
+            Value::Dict(
+                elements()
+                    .into_iter()
+                    .map(|key| (key, Value::Unit))
+                    .collect::<Vec<_>>()
+                    .into(),
+            ),
+        ),
+        ("bytes", Value::Bytes(vec![0; SIZE as usize].into())),
+    ];
+
+    for (name, value) in values {
+        let mut output = io::sink();
+        let mut compiler = Compiler::new(&mut output);
+        compiler.define_global("value", value);
+        let tokens = compiler.scan_line(&format!(
+            "
+            f := fn x -> x
+            i := 0
+            while i < {CALLS}:
+                f! value
+                i += 1
+            "
+        ));
+        let exprs = compiler.parse(tokens);
+
+        let start = Instant::now();
+        compiler.evaluate(exprs);
+        println!("{name:>6}: {:?}", start.elapsed());
+    }
+}
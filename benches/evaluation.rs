@@ -0,0 +1,63 @@
+//! Times programs that make many calls, deep recursion and long loops. Run
+//! with `cargo bench --bench evaluation`.
+
+use std::{io, time::Instant};
+
+use zeal::Compiler;
+
+const PROGRAMS: &[(&str, &str)] = &[
+    (
+        "fib",
+        "
+        fib := fn n -> if n < 2: n else: (fib! (n - 1)) + (fib! (n - 2))
+        fib! 24
+        ",
+    ),
+    (
+        "deep",
+        "
+        sum := fn n -> if n == 0: 0 else: n + (sum! (n - 1))
+        i := 0
+        while i < 100:
+            sum! 2000
+            i += 1
+        ",
+    ),
+    (
+        "tail",
+        "
+        count := fn n acc -> if n == 0: acc else: count! (n - 1) (acc + 1)
+        count! 1000000 0
+        ",
+    ),
+    (
+        "while",
+        "
+        i := 0
+        total := 0
+        while i < 1000000:
+            total += i % 7
+            i += 1
+        ",
+    ),
+    (
+        "reduce",
+        "
+        xs := range! 0 300000
+        reduce! (fn total x -> total + x % 7) 0 xs
+        ",
+    ),
+];
+
+fn main() {
+    for (name, source) in PROGRAMS {
+        let mut output = io::sink();
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(source);
+        let exprs = compiler.parse(tokens);
+
+        let start = Instant::now();
+        compiler.evaluate(exprs);
+        println!("{name:>6}: {:?}", start.elapsed());
+    }
+}
@@ -0,0 +1,50 @@
+//! Times building lists one element at a time with `push!`. Run with
+//! `cargo bench --bench lists`.
+
+use std::{io, time::Instant};
+
+use zeal::Compiler;
+
+const PROGRAMS: &[(&str, &str)] = &[
+    (
+        "recursive",
+        "
+        build := fn n acc -> if n == 0: acc else: build! (n - 1) (push! acc n)
+        build! 20000 []
+        ",
+    ),
+    (
+        "while",
+        "
+        xs := []
+        i := 0
+        while i < 20000:
+            xs = push! xs i
+            i += 1
+        ",
+    ),
+    (
+        "branching",
+        "
+        base := push! (push! [] 1) 2
+        i := 0
+        while i < 20000:
+            left := push! base i
+            right := push! base (i + 1)
+            i += 1
+        ",
+    ),
+];
+
+fn main() {
+    for (name, source) in PROGRAMS {
+        let mut output = io::sink();
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(source);
+        let exprs = compiler.parse(tokens);
+
+        let start = Instant::now();
+        compiler.evaluate(exprs);
+        println!("{name:>9}: {:?}", start.elapsed());
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{interpreter::is_builtin, parser::Expr, scanner::Location};
+
+/// A problem found in a program before it runs.
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Reports names that are never declared, and calls that pass a lambda
+/// literal, or a name declared as one, the wrong number of arguments.
+///
+/// Scopes mirror the environments the interpreter creates. Names are
+/// resolved when a call happens, so every declaration in a scope is visible
+/// throughout it, as if hoisted. Calls through anything else, such as a
+/// reassigned variable or a method, can't be checked.
+pub fn check(exprs: &[Expr]) -> Vec<Diagnostic> {
+    let mut checker = Checker::default();
+    for expr in exprs {
+        checker.survey(expr);
+    }
+    checker.check_scope(exprs, HashMap::default());
+    checker.diagnostics
+}
+
+#[derive(Default)]
+struct Checker {
+    /// The names declared in each enclosing scope, innermost last, with the
+    /// number of parameters of the lambda literal they were declared as.
+    scopes: Vec<HashMap<String, Option<usize>>>,
+    /// Names assigned to anywhere, whose arity can't be known.
+    reassigned: HashSet<String>,
+    /// Whether a bare import may declare names this pass can't see.
+    imports: bool,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn survey(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Assignment(lhs, ..) => {
+                if let Expr::Identifier(name, _) = lhs.as_ref() {
+                    self.reassigned.insert(name.clone());
+                }
+            }
+            Expr::Import(_) => self.imports = true,
+            _ => {}
+        }
+        for child in expr.children() {
+            self.survey(child);
+        }
+    }
+
+    /// Checks a list of statements in a new scope that already holds
+    /// `names`.
+    fn check_scope(&mut self, exprs: &[Expr], mut names: HashMap<String, Option<usize>>) {
+        for expr in exprs {
+            if let Expr::Declaration(lhs, _, init) = expr {
+                if let Expr::Identifier(name, _) = lhs.as_ref() {
+                    let arity = init
+                        .as_deref()
+                        .and_then(lambda_arity)
+                        .filter(|_| !self.reassigned.contains(name));
+                    names.insert(name.clone(), arity);
+                } else {
+                    pattern_names(lhs, &mut names);
+                }
+            }
+        }
+
+        self.scopes.push(names);
+        for expr in exprs {
+            self.check_expr(expr);
+        }
+        self.scopes.pop();
+    }
+
+    fn check_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Identifier(name, location) => self.check_name(name, *location),
+            // The name of a declaration is in scope already.
+            Expr::Declaration(_, _, init) => {
+                if let Some(init) = init {
+                    self.check_expr(init);
+                }
+            }
+            Expr::Block(exprs) => self.check_scope(exprs, HashMap::default()),
+            Expr::Lambda(params, body, _) => {
+                let mut names = HashMap::default();
+                for param in params.iter() {
+                    pattern_names(param, &mut names);
+                }
+                self.check_scope(body, names);
+            }
+            Expr::CaptureByValue(captures, lambda) => {
+                let location = lambda.location();
+                for name in captures {
+                    if let Some(location) = location {
+                        self.check_name(name, location);
+                    }
+                }
+                self.check_expr(lambda);
+            }
+            Expr::Match(scrutinee, arms) => {
+                self.check_expr(scrutinee);
+                for (pattern, guard, body) in arms {
+                    let mut names = HashMap::default();
+                    pattern_names(pattern, &mut names);
+                    self.scopes.push(names);
+                    if let Some(guard) = guard {
+                        self.check_expr(guard);
+                    }
+                    self.check_expr(body);
+                    self.scopes.pop();
+                }
+            }
+            Expr::Comprehension(body, pattern, iterable, guard) => {
+                self.check_expr(iterable);
+                let mut names = HashMap::default();
+                pattern_names(pattern, &mut names);
+                self.scopes.push(names);
+                if let Some(guard) = guard {
+                    self.check_expr(guard);
+                }
+                self.check_expr(body);
+                self.scopes.pop();
+            }
+            Expr::Try(body, name, handler) => {
+                self.check_expr(body);
+                self.scopes.push(HashMap::from([(name.clone(), None)]));
+                self.check_expr(handler);
+                self.scopes.pop();
+            }
+            Expr::FunctionCall(callee, args, _) => {
+                match callee.as_ref() {
+                    // A method name is resolved against the receiver at run time.
+                    Expr::Get(obj, _) => self.check_expr(obj),
+                    callee => self.check_expr(callee),
+                }
+                for arg in args {
+                    self.check_expr(arg);
+                }
+                self.check_arity(callee, args);
+            }
+            expr => {
+                for child in expr.children() {
+                    self.check_expr(child);
+                }
+            }
+        }
+    }
+
+    fn check_name(&mut self, name: &str, location: Location) {
+        let declared = self.scopes.iter().any(|scope| scope.contains_key(name));
+        if declared || self.imports || is_builtin(name) {
+            return;
+        }
+        self.diagnostics.push(Diagnostic {
+            line: location.line,
+            message: format!("Undefined variable {name:?}"),
+        });
+    }
+
+    fn check_arity(&mut self, callee: &Expr, args: &[Expr]) {
+        let (function, arity) = match callee {
+            Expr::Identifier(name, _) => {
+                let arity = self
+                    .scopes
+                    .iter()
+                    .rev()
+                    .find_map(|scope| scope.get(name))
+                    .copied()
+                    .flatten();
+                (format!("{name:?}"), arity)
+            }
+            callee => (String::from("Lambda"), lambda_arity(callee)),
+        };
+
+        match arity {
+            Some(arity) if arity != args.len() => self.diagnostics.push(Diagnostic {
+                line: callee.location().map_or(0, |location| location.line),
+                message: format!(
+                    "{function} takes {arity} arguments but was given {}",
+                    args.len()
+                ),
+            }),
+            _ => {}
+        }
+    }
+}
+
+/// The number of parameters of a lambda literal.
+fn lambda_arity(expr: &Expr) -> Option<usize> {
+    match expr {
+        Expr::Lambda(params, _, _) => Some(params.len()),
+        Expr::Group(expr) | Expr::CaptureByValue(_, expr) => lambda_arity(expr),
+        _ => None,
+    }
+}
+
+/// Collects the names a parameter or match pattern binds.
+fn pattern_names(pattern: &Expr, names: &mut HashMap<String, Option<usize>>) {
+    match pattern {
+        Expr::Identifier(name, _) => {
+            names.insert(name.clone(), None);
+        }
+        pattern => {
+            for child in pattern.children() {
+                pattern_names(child, names);
+            }
+        }
+    }
+}
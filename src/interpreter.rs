@@ -1,238 +1,2755 @@
-use std::{cell::RefCell, collections::HashMap, io::Write, rc::Rc};
+use std::{
+    cell::{Cell, OnceCell, RefCell},
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    io::{BufRead, Write},
+    mem,
+    path::{Path, PathBuf},
+    rc::Rc,
+    thread,
+    time::Duration,
+};
 
 use crate::{
-    parser::{Expr, Value},
-    scanner::{Token, TokenType},
+    json,
+    parser::{Expr, Key, List, Native, Parser, Value},
+    scanner::{Location, Scanner, Token, TokenType},
 };
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuntimeError {
+    TypeError(String),
+    UndefinedVariable(String),
+    UndefinedFunction(String),
+    DivisionByZero,
+    /// Int arithmetic whose result doesn't fit in an int.
+    IntegerOverflow,
+    NoMatch(Value),
+    OutsideLoop,
+    OutsideFunction,
+    Uncaught(Value),
+    AssertionFailed(String),
+    StackOverflow,
+    EmptyList(String),
+    UndefinedField(String),
+    ImportFailed(String),
+    CyclicImport(String),
+    GuardFellThrough,
+    /// A call to a builtin the embedder hasn't allowed.
+    Forbidden(String),
+    /// An error raised by the expression on `line`.
+    Located {
+        line: usize,
+        error: Box<RuntimeError>,
+    },
+    /// An error raised inside function calls, with the calls that were
+    /// active at the time, outermost first.
+    Traced {
+        error: Box<RuntimeError>,
+        trace: Vec<Frame>,
+    },
+}
+
+/// A lambda call in progress: the function's name, or `<lambda>` for a
+/// function without one, and the line of the call.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub function: String,
+    pub line: usize,
+}
+
+impl RuntimeError {
+    /// Attaches the line of `location` to the error, unless it already has one.
+    pub fn at(self, location: Location) -> Self {
+        match self {
+            RuntimeError::Located { .. } | RuntimeError::Traced { .. } => self,
+            error => RuntimeError::Located {
+                line: location.line,
+                error: Box::new(error),
+            },
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::TypeError(message) => write!(f, "Type error: {message}"),
+            RuntimeError::UndefinedVariable(name) => write!(f, "Undefined Variable {name:?}"),
+            RuntimeError::UndefinedFunction(name) => write!(f, "Undefined function {name:?}"),
+            RuntimeError::DivisionByZero => write!(f, "Division by zero"),
+            RuntimeError::IntegerOverflow => write!(f, "Integer overflow"),
+            RuntimeError::NoMatch(value) => write!(f, "No match arm for {value:?}"),
+            RuntimeError::OutsideLoop => write!(f, "break or continue outside of a loop"),
+            RuntimeError::OutsideFunction => write!(f, "return outside of a function"),
+            RuntimeError::Uncaught(value) => write!(f, "Uncaught exception: {value:?}"),
+            RuntimeError::AssertionFailed(message) => write!(f, "Assertion failed: {message}"),
+            RuntimeError::StackOverflow => write!(f, "Stack overflow"),
+            RuntimeError::EmptyList(name) => write!(f, "Cannot take {name} of an empty list"),
+            RuntimeError::UndefinedField(name) => write!(f, "Undefined field {name:?}"),
+            RuntimeError::ImportFailed(message) => write!(f, "Import failed: {message}"),
+            RuntimeError::CyclicImport(path) => write!(f, "Cyclic import of {path:?}"),
+            RuntimeError::GuardFellThrough => write!(f, "guard else branch did not exit"),
+            RuntimeError::Forbidden(name) => write!(f, "{name} is not allowed"),
+            RuntimeError::Located { line, error } => write!(f, "{error} on line {line}"),
+            RuntimeError::Traced { error, trace } => {
+                write!(f, "{error}")?;
+                for frame in trace.iter().rev() {
+                    write!(f, "\n  in {} called on line {}", frame.function, frame.line)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// Non-local exits that unwind through the interpreter until something
+/// handles them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ControlFlow {
+    /// Carries the label of the loop to leave, if any.
+    Break(Option<String>),
+    /// Carries the label of the loop to continue, if any.
+    Continue(Option<String>),
+    Return(Value),
+    Throw(Value),
+    Error(RuntimeError),
+}
+
+impl From<RuntimeError> for ControlFlow {
+    fn from(err: RuntimeError) -> Self {
+        ControlFlow::Error(err)
+    }
+}
+
+impl From<ControlFlow> for RuntimeError {
+    /// Converts a control flow that escaped to a function or program
+    /// boundary into the error it represents there.
+    fn from(flow: ControlFlow) -> Self {
+        match flow {
+            ControlFlow::Break(_) | ControlFlow::Continue(_) => RuntimeError::OutsideLoop,
+            ControlFlow::Return(_) => RuntimeError::OutsideFunction,
+            ControlFlow::Throw(value) => RuntimeError::Uncaught(value),
+            ControlFlow::Error(err) => err,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Environment {
     parent: Option<Rc<RefCell<Environment>>>,
-    values: HashMap<String, Value>,
+    /// How many of the parent's bindings this scope sees: those declared
+    /// before it was opened.
+    parent_len: usize,
+    /// Each binding, with the order it was first declared in.
+    values: HashMap<String, (usize, Value)>,
 }
 
 impl Environment {
+    /// A scope nested in `parent` that sees the first `visible` bindings
+    /// declared there. Globals are always visible, so top-level functions can
+    /// refer to ones declared after them.
+    pub fn nested(parent: Rc<RefCell<Environment>>, visible: usize) -> Self {
+        Environment {
+            parent: Some(parent),
+            parent_len: visible,
+            values: HashMap::new(),
+        }
+    }
+
+    /// How many bindings have been declared in this scope.
+    pub fn declared(&self) -> usize {
+        self.values.len()
+    }
+
     pub fn get(&self, identifier: &str) -> Option<Value> {
-        self.values.get(identifier).cloned().or_else(|| {
-            self.parent
-                .as_ref()
-                .and_then(|parent| parent.borrow().get(identifier))
-        })
+        self.lookup(identifier, usize::MAX)
     }
 
-    pub fn set(&mut self, identifier: &str, value: Value) {
-        if !self.values.contains_key(identifier) {
-            let parent = self
+    fn lookup(&self, identifier: &str, visible: usize) -> Option<Value> {
+        match self.values.get(identifier) {
+            Some((index, value)) if self.parent.is_none() || *index < visible => {
+                Some(value.clone())
+            }
+            _ => self
                 .parent
-                .as_mut()
-                .unwrap_or_else(|| panic!("Error assigning to undefined variable: {identifier:?}"));
-            parent.borrow_mut().set(identifier, value);
-        } else {
-            self.values.insert(identifier.to_string(), value);
+                .as_ref()
+                .and_then(|parent| parent.borrow().lookup(identifier, self.parent_len)),
+        }
+    }
+
+    pub fn set(&mut self, identifier: &str, value: Value) -> Result<(), RuntimeError> {
+        self.update(identifier, value, usize::MAX)
+    }
+
+    fn update(
+        &mut self,
+        identifier: &str,
+        value: Value,
+        visible: usize,
+    ) -> Result<(), RuntimeError> {
+        let is_global = self.parent.is_none();
+        match self.values.get_mut(identifier) {
+            Some((index, slot)) if is_global || *index < visible => {
+                *slot = value;
+                Ok(())
+            }
+            _ => {
+                let parent_len = self.parent_len;
+                let parent = self
+                    .parent
+                    .as_mut()
+                    .ok_or_else(|| RuntimeError::UndefinedVariable(identifier.to_string()))?;
+                parent.borrow_mut().update(identifier, value, parent_len)
+            }
         }
     }
 
+    /// Binds `identifier` in this scope. Declaring a name again replaces its
+    /// value but keeps its place, so closures that could see it still do.
     pub fn define(&mut self, identifier: &str, value: Value) {
-        self.values.insert(identifier.to_string(), value);
+        match self.values.get_mut(identifier) {
+            Some((_, slot)) => *slot = value,
+            None => {
+                let index = self.values.len();
+                self.values.insert(identifier.to_string(), (index, value));
+            }
+        }
+    }
+
+    /// Reads a top-level binding of a module.
+    pub fn member(module: &RefCell<Environment>, name: &str) -> Result<Value, RuntimeError> {
+        module
+            .borrow()
+            .values
+            .get(name)
+            .map(|(_, value)| value.clone())
+            .ok_or_else(|| RuntimeError::UndefinedField(name.to_string()))
+    }
+
+    /// Replaces a top-level binding of a module.
+    pub fn set_member(
+        module: &RefCell<Environment>,
+        name: &str,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        match module.borrow_mut().values.get_mut(name) {
+            Some((_, member)) => {
+                *member = value;
+                Ok(())
+            }
+            None => Err(RuntimeError::UndefinedField(name.to_string())),
+        }
+    }
+
+    /// Binds `value` to the names in a parameter pattern, unpacking tuples
+    /// element by element.
+    pub fn define_pattern(&mut self, pattern: &Expr, value: Value) -> Result<(), RuntimeError> {
+        match (pattern, value) {
+            (Expr::Identifier(name, _), value) => self.define(name, value),
+            (Expr::Group(pattern), value) => self.define_pattern(pattern, value)?,
+            (Expr::Tuple(patterns), Value::Tuple(values)) if patterns.len() == values.len() => {
+                for (pattern, value) in patterns.iter().zip(values.iter()) {
+                    self.define_pattern(pattern, value.clone())?;
+                }
+            }
+            (Expr::Tuple(patterns), value) => {
+                return Err(RuntimeError::TypeError(format!(
+                    "Expected a tuple of {} elements to destructure, found {value:?}",
+                    patterns.len()
+                )))
+            }
+            (pattern, _) => panic!("Invalid function parameter {pattern:?}"),
+        }
+        Ok(())
     }
 }
 
-pub struct Interpreter<'a, T: Write> {
+pub(crate) const BUILTINS: &[&str] = &[
+    "contains",
+    "union",
+    "intersect",
+    "throw",
+    "some",
+    "each",
+    "first",
+    "head",
+    "last",
+    "rest",
+    "tail",
+    "push",
+    "slice",
+    "index_of",
+    "trim",
+    "starts_with",
+    "ends_with",
+    "replace",
+    "chars",
+    "to_list",
+    "to_int",
+    "from_int",
+    "to_bool",
+    "sum",
+    "product",
+    "count",
+    "all",
+    "any",
+    "debug",
+    "arity",
+    "reduce",
+    "flip",
+    "memoize",
+    "clone",
+    "take",
+    "drop",
+    "take_while",
+    "drop_while",
+    "flatten",
+    "flat_map",
+    "group_by",
+    "unique",
+    "is_empty",
+    "pad_left",
+    "pad_right",
+    "range",
+    "dump_env",
+    "bytes",
+    "byte_at",
+    "len",
+    "to_string",
+    "read_file",
+    "write_file",
+    "env",
+    "sleep",
+    "parse_json",
+    "to_json",
+    "input",
+    "scan",
+    "hex",
+    "bin",
+    "to_tuple",
+    "apply",
+    "delay",
+    "force",
+];
+
+/// Binary operators that can be used as two-argument functions, as in
+/// `reduce! (+) 0 xs`.
+pub(crate) const OPERATORS: &[(&str, TokenType)] = &[
+    ("+", TokenType::Plus),
+    ("-", TokenType::Minus),
+    ("*", TokenType::Star),
+    ("/", TokenType::Slash),
+    ("//", TokenType::SlashSlash),
+    ("%", TokenType::Mod),
+    ("%%", TokenType::ModMod),
+    ("==", TokenType::EqualEqual),
+    ("!=", TokenType::BangEqual),
+    ("<", TokenType::Less),
+    ("<=", TokenType::LessEqual),
+    (">", TokenType::Greater),
+    (">=", TokenType::GreaterEqual),
+    ("&&", TokenType::AndAnd),
+    ("||", TokenType::OrOr),
+];
+
+/// Whether `name` refers to a builtin function or operator when nothing else
+/// is bound to it.
+pub(crate) fn is_builtin(name: &str) -> bool {
+    BUILTINS.contains(&name) || OPERATORS.iter().any(|(operator, _)| *operator == name)
+}
+
+/// How many lambda calls may be in progress at once before evaluation stops
+/// with a stack overflow error. Tail calls replace the call they're in, so
+/// don't add to the count.
+const MAX_CALLS: usize = 40_000;
+
+/// What the evaluator does next.
+enum Step<'e> {
+    /// Evaluates an expression.
+    Eval(&'e Expr),
+    /// Evaluates an expression in tail position, deferring a call to a lambda
+    /// to the function the expression is in. An expression is in tail
+    /// position when it is the last expression of a function body, or a
+    /// branch of an `if`, the inner expression of a group, or the operand of
+    /// a `return` that is itself in tail position.
+    EvalTail(&'e Expr),
+    /// Calls a function, with the frame to show in traces for a lambda.
+    Call(Value, Vec<Value>, Option<Frame>),
+    /// Hands how an expression exited to the innermost continuation.
+    Done(Result<Value, ControlFlow>),
+    /// Hands a lambda call in tail position to the function it replaces, so
+    /// tail recursion runs in constant space.
+    TailCall(Value, Vec<Value>),
+}
+
+impl<'e> Step<'e> {
+    fn eval(expr: &'e Expr, tail: bool) -> Self {
+        if tail {
+            Step::EvalTail(expr)
+        } else {
+            Step::Eval(expr)
+        }
+    }
+
+    fn failed(flow: ControlFlow) -> Self {
+        Step::Done(Err(flow))
+    }
+}
+
+/// What is left to do once the expression being evaluated exits. The
+/// evaluator keeps these on a stack, innermost last, instead of recursing, so
+/// deep recursion in a program can't overflow the host's stack. Those that
+/// replace the scope hold the one to restore, and restore it however the
+/// expression exits.
+enum Continuation<'e> {
+    /// Ends a traced expression, logging its value at this indent.
+    Leave(String),
+    /// Restores the scope from before a block, match arm or handler.
+    Scope(Rc<RefCell<Environment>>),
+    /// Gives a statement the unit value.
+    Unit,
+    /// Evaluates the rest of a sequence, whose last value is its value. The
+    /// last expression of a function body is in tail position.
+    Sequence(&'e [Expr], bool),
+    /// Evaluates the rest of a list of expressions, gathering their values.
+    Elements(&'e [Expr], Vec<Value>, Collection<'e>),
+    /// Evaluates the rest of a dict literal, starting with the value of the
+    /// first entry if its key has been evaluated.
+    Dict(&'e [(Expr, Expr)], Option<Value>, Vec<(Value, Value)>),
+    Propagate,
+    Return,
+    Unary(&'e Token),
+    /// Evaluates the right operand of a binary operator.
+    Binary(&'e Token, &'e Expr),
+    /// Applies a binary operator to its left operand and the value.
+    Operator(&'e Token, Value),
+    /// Evaluates the right operand of `&&` or `||` unless the left one
+    /// decides the result.
+    Logical(&'e Token, &'e Expr),
+    /// Checks the first of the comparisons left in a chain, whose left
+    /// operand is given once the first operand has been evaluated.
+    Chain(Option<Value>, &'e [(Token, Expr)]),
+    Declare(&'e Expr),
+    Assign(&'e Expr, Location),
+    /// Sets a field of the value, which `obj` evaluated to, for an assignment
+    /// at `location` whose value is `result`.
+    AssignField {
+        obj: &'e Expr,
+        name: &'e str,
+        value: Value,
+        location: Location,
+        result: Value,
+    },
+    Get(&'e str),
+    /// Evaluates one of the branches of an `if`, which are in tail position
+    /// if the `if` is.
+    If(&'e Expr, Option<&'e Expr>, bool),
+    /// Evaluates the else branch of a guard whose condition failed.
+    Guard(&'e Expr),
+    /// Fails once the else branch of a guard finishes without exiting.
+    GuardElse,
+    /// Runs the handler of a `try` with the exception bound to the name.
+    Try(&'e str, &'e Expr),
+    /// Evaluates the arguments of a call once its callee has been.
+    Callee(CallSite<'e>),
+    /// Looks up the function `obj.name! args` calls once `obj` has been
+    /// evaluated.
+    Method(CallSite<'e>),
+    /// Returns from a lambda call to the caller's scope. A traced call has a
+    /// frame to pop.
+    Function {
+        scope: Rc<RefCell<Environment>>,
+        traced: bool,
+    },
+    /// Finishes evaluating a file, giving its scope as a module or importing
+    /// its bindings.
+    Module {
+        path: PathBuf,
+        scope: Rc<RefCell<Environment>>,
+        coverage: Option<BTreeMap<usize, bool>>,
+        import: bool,
+    },
+    /// Tries the arms of a match on the value.
+    Match(&'e [(Expr, Option<Expr>, Expr)]),
+    /// Runs the body of the first arm if its guard holds, and otherwise tries
+    /// the other arms.
+    MatchGuard(
+        Value,
+        &'e [(Expr, Option<Expr>, Expr)],
+        Rc<RefCell<Environment>>,
+    ),
+    /// Compares the value with the cases of a switch.
+    Switch(&'e [(Expr, Expr)], Option<&'e Expr>),
+    /// Runs the body of the first case if it equals the scrutinee, and
+    /// otherwise tries the other cases.
+    SwitchCase(Value, &'e [(Expr, Expr)], Option<&'e Expr>),
+    /// Runs or ends a `while` or `repeat` after its condition.
+    LoopCondition(&'e Expr, Option<&'e str>),
+    /// Goes on with a `while`, `repeat` or `loop` after its body.
+    LoopBody(&'e Expr, Option<&'e str>),
+    /// Runs or ends a collecting loop after its condition.
+    CollectCondition(&'e Expr, &'e Expr, Vec<Value>),
+    /// Gathers the value of a collecting loop's body.
+    CollectBody(&'e Expr, &'e Expr, Vec<Value>),
+    /// Starts a comprehension once its iterable has been evaluated.
+    Iterable(&'e Expr),
+    /// Goes on with a comprehension after its guard, if `true`, or its body.
+    Comprehension(Box<Comprehension<'e>>, bool),
+    /// Goes on with a builtin calling a function on each element of a list.
+    Iteration(Box<Iteration>),
+    /// Caches the result of a memoized function for the arguments.
+    Memoize(Rc<Native>, Vec<Key>),
+    /// Caches the result of forcing a thunk.
+    Force(Rc<RefCell<Option<Value>>>),
+}
+
+/// A call being evaluated, and whether it's in tail position.
+#[derive(Clone, Copy)]
+struct CallSite<'e> {
+    callee: &'e Expr,
+    args: &'e [Expr],
+    location: Location,
+    tail: bool,
+}
+
+/// What the values of a list of expressions are gathered into.
+enum Collection<'e> {
+    Tuple,
+    List,
+    Set,
+    /// The arguments of a call to the function.
+    Arguments(Value, CallSite<'e>),
+}
+
+/// A comprehension partway through the elements of its iterable.
+struct Comprehension<'e> {
+    body: &'e Expr,
+    pattern: &'e Expr,
+    guard: Option<&'e Expr>,
+    elements: std::vec::IntoIter<Value>,
+    values: Vec<Value>,
+    /// The scope the comprehension is in, restored after each element.
+    scope: Rc<RefCell<Environment>>,
+}
+
+/// A builtin partway through calling a function on each element of a list in
+/// turn. The function was last called with the element at `index`.
+struct Iteration {
+    name: String,
+    func: Value,
+    elements: List,
+    index: usize,
+    fold: Fold,
+}
+
+/// What a builtin that iterates over a list makes of each result.
+enum Fold {
+    Each,
+    TakeWhile,
+    DropWhile,
+    Count(i32),
+    All,
+    Any,
+    FlatMap(Vec<Value>),
+    GroupBy(Vec<(Value, Vec<Value>)>),
+    Reduce(Value),
+    /// Every total so far, starting with the initial one.
+    Scan(Vec<Value>),
+}
+
+impl Iteration {
+    /// The arguments to call the function with for the current element.
+    fn args(&self) -> Vec<Value> {
+        let element = self.elements[self.index].clone();
+        match &self.fold {
+            Fold::Reduce(total) => vec![total.clone(), element],
+            Fold::Scan(totals) => vec![totals[totals.len() - 1].clone(), element],
+            _ => vec![element],
+        }
+    }
+
+    /// The value of the builtin, having stopped before the element at `index`.
+    fn finish(self) -> Value {
+        match self.fold {
+            Fold::Each => Value::Unit,
+            Fold::TakeWhile => Value::List(self.elements.slice(0..self.index)),
+            Fold::DropWhile => Value::List(self.elements.slice(self.index..self.elements.len())),
+            Fold::Count(count) => Value::Int(count),
+            Fold::All => Value::Bool(true),
+            Fold::Any => Value::Bool(false),
+            Fold::FlatMap(flattened) => Value::List(flattened.into()),
+            // Keys follow the same rules as dict literals, and groups are in
+            // the order their keys first appear.
+            Fold::GroupBy(groups) => Value::Dict(
+                groups
+                    .into_iter()
+                    .map(|(key, group)| (key, Value::List(group.into())))
+                    .collect(),
+            ),
+            Fold::Reduce(total) => total,
+            Fold::Scan(totals) => Value::List(totals.into()),
+        }
+    }
+}
+
+/// Function bodies and imported files kept until an evaluation ends, so its
+/// continuations can refer to the expressions in them. Each is kept once,
+/// however many times it's evaluated.
+struct Bodies<'e> {
+    last: Cell<&'e KeptBody>,
+    kept: RefCell<HashMap<*const Expr, &'e [Expr]>>,
+}
+
+/// A link in the list of kept bodies, empty until a body is kept in it.
+#[derive(Default)]
+struct KeptBody(OnceCell<(Rc<[Expr]>, Box<KeptBody>)>);
+
+impl<'e> Bodies<'e> {
+    fn new(first: &'e KeptBody) -> Self {
+        Bodies {
+            last: Cell::new(first),
+            kept: RefCell::default(),
+        }
+    }
+
+    fn keep(&self, body: &Rc<[Expr]>) -> &'e [Expr] {
+        if let Some(kept) = self.kept.borrow().get(&body.as_ptr()) {
+            return kept;
+        }
+        let (kept, next) = self
+            .last
+            .get()
+            .0
+            .get_or_init(|| (body.clone(), Box::default()));
+        self.last.set(next);
+        self.kept.borrow_mut().insert(body.as_ptr(), kept);
+        kept
+    }
+}
+
+/// The continuations of an evaluation, innermost last, and the bodies they
+/// refer to.
+struct Stack<'e> {
+    continuations: Vec<Continuation<'e>>,
+    bodies: &'e Bodies<'e>,
+    /// How many of the continuations are lambda calls.
+    calls: usize,
+}
+
+impl<'e> Stack<'e> {
+    fn push(&mut self, continuation: Continuation<'e>) {
+        self.continuations.push(continuation);
+    }
+
+    /// Evaluates `expr`, then carries on with `continuation`.
+    fn then(&mut self, continuation: Continuation<'e>, expr: &'e Expr) -> Step<'e> {
+        self.push(continuation);
+        Step::Eval(expr)
+    }
+}
+
+/// Counts from `start` towards `end`, which is excluded, by `step`.
+fn range(start: i32, end: i32, step: i32) -> Vec<Value> {
+    let mut values = Vec::default();
+    let mut current = Some(start);
+    while let Some(value) =
+        current.filter(|&value| if step > 0 { value < end } else { value > end })
+    {
+        values.push(Value::Int(value));
+        current = value.checked_add(step);
+    }
+    values
+}
+
+/// Writes `value` in base 16 for `hex!` or base 2 for `bin!`, zero-padded to
+/// at least `width` digits. Negative numbers get a minus sign rather than
+/// being written in two's complement, and the sign doesn't count towards the
+/// width.
+fn to_radix(name: &str, value: i32, width: i32) -> String {
+    let width = width.max(0) as usize;
+    let digits = if name == "hex" {
+        format!("{:0width$x}", value.unsigned_abs())
+    } else {
+        format!("{:0width$b}", value.unsigned_abs())
+    };
+    if value < 0 {
+        format!("-{digits}")
+    } else {
+        digits
+    }
+}
+
+/// The indices `slice!` takes from a sequence of `len` elements. Negative
+/// indices count back from the end, and out of range indices are clamped to
+/// the sequence, so slicing never fails.
+fn slice_range(len: usize, start: i32, end: i32) -> std::ops::Range<usize> {
+    let resolve = |index: i32| {
+        let index = if index < 0 {
+            len as i64 + index as i64
+        } else {
+            index as i64
+        };
+        index.clamp(0, len as i64) as usize
+    };
+    let (start, end) = (resolve(start), resolve(end));
+    start..end.max(start)
+}
+
+/// Adds `element` to, or multiplies it into, the running `total` of `sum!` or
+/// `product!`. The total becomes a float once a float element is seen.
+fn accumulate(name: &str, total: Value, element: &Value) -> Result<Value, RuntimeError> {
+    let is_sum = name == "sum";
+    let (total, element) = match (total, element) {
+        (Value::Int(total), Value::Int(element)) => {
+            return checked(if is_sum {
+                total.checked_add(*element)
+            } else {
+                total.checked_mul(*element)
+            });
+        }
+        (Value::Int(total), Value::Float(element)) => (total as f64, *element),
+        (Value::Float(total), Value::Int(element)) => (total, *element as f64),
+        (Value::Float(total), Value::Float(element)) => (total, *element),
+        (_, element) => {
+            return Err(RuntimeError::TypeError(format!(
+                "Cannot take the {name} of a list containing {}",
+                element.type_name()
+            )))
+        }
+    };
+    Ok(Value::Float(if is_sum {
+        total + element
+    } else {
+        total * element
+    }))
+}
+
+pub struct Interpreter<'a, T: Write, I: BufRead> {
     environment: Rc<RefCell<Environment>>,
     output: &'a mut T,
+    /// Where `input!` reads lines from.
+    input: I,
+    depth: usize,
+    /// The lambda calls in progress, innermost last. A call in tail position
+    /// takes over its caller's frame, so only the caller shows up in a trace.
+    frames: Vec<Frame>,
+    /// The files being evaluated, innermost last. Imports are resolved
+    /// relative to the last one.
+    imports: Vec<PathBuf>,
+    /// Every file imported so far, so each is only evaluated once.
+    modules: HashMap<PathBuf, Rc<RefCell<Environment>>>,
+    /// Whether `read_file!` and `write_file!` may touch the file system.
+    allow_fs: bool,
+    /// Whether `env!` may read environment variables.
+    allow_env: bool,
+    /// What `sleep!` calls to pause, so embedders and tests can stub it out.
+    sleeper: Box<dyn FnMut(Duration)>,
+    /// Where each expression and its value is logged, if anywhere.
+    trace: Option<Box<dyn Write>>,
+    /// Whether each line of the program holding an expression has run, once
+    /// coverage is on. Imported files aren't covered.
+    coverage: Option<BTreeMap<usize, bool>>,
 }
 
-impl<'a, T: Write> Interpreter<'a, T> {
-    pub fn new(output: &'a mut T) -> Self {
+impl<'a, T: Write, I: BufRead> Interpreter<'a, T, I> {
+    pub fn new(output: &'a mut T, input: I) -> Self {
         Self {
             environment: Rc::new(RefCell::new(Environment::default())),
             output,
+            input,
+            depth: 0,
+            frames: Vec::default(),
+            imports: Vec::default(),
+            modules: HashMap::default(),
+            allow_fs: false,
+            allow_env: false,
+            sleeper: Box::new(thread::sleep),
+            trace: None,
+            coverage: None,
         }
     }
 
-    pub fn interpret(&mut self, mut exprs: Vec<Expr>) -> Vec<Value> {
-        exprs
-            .drain(..)
-            .map(|expr| self.interpret_expr(&expr))
-            .collect()
+    /// Evaluates the contents of the file at `path`, resolving its imports
+    /// relative to it.
+    pub fn interpret_file(&mut self, path: &Path, exprs: Vec<Expr>) -> Vec<Value> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.imports.push(path);
+        let values = self.interpret(exprs);
+        self.imports.pop();
+        values
     }
 
-    pub fn interpret_expr(&mut self, expr: &Expr) -> Value {
-        match expr {
+    pub fn allow_fs(&mut self, allow: bool) {
+        self.allow_fs = allow;
+    }
+
+    pub fn allow_env(&mut self, allow: bool) {
+        self.allow_env = allow;
+    }
+
+    pub fn read_line(&mut self, line: &mut String) -> std::io::Result<usize> {
+        self.input.read_line(line)
+    }
+
+    pub fn set_sleeper(&mut self, sleeper: impl FnMut(Duration) + 'static) {
+        self.sleeper = Box::new(sleeper);
+    }
+
+    pub fn set_trace(&mut self, trace: impl Write + 'static) {
+        self.trace = Some(Box::new(trace));
+    }
+
+    pub fn enable_coverage(&mut self) {
+        self.coverage.get_or_insert_with(BTreeMap::default);
+    }
+
+    pub fn coverage(&self) -> Option<&BTreeMap<usize, bool>> {
+        self.coverage.as_ref()
+    }
+
+    /// Binds `name` in the top-level environment, whatever scope is current.
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        let mut environment = self.environment.clone();
+        loop {
+            let parent = environment.borrow().parent.clone();
+            match parent {
+                Some(parent) => environment = parent,
+                None => break,
+            }
+        }
+        environment.borrow_mut().define(name, value);
+    }
+
+    pub fn interpret(&mut self, exprs: Vec<Expr>) -> Vec<Value> {
+        self.try_interpret(exprs)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Evaluates `exprs` in turn. Nothing recurses on the host's stack, so
+    /// deep recursion in a program reports an error rather than aborting.
+    pub fn try_interpret(&mut self, exprs: Vec<Expr>) -> Result<Vec<Value>, RuntimeError> {
+        if let Some(coverage) = &mut self.coverage {
+            for expr in &exprs {
+                coverable_lines(expr, coverage);
+            }
+        }
+        let first = KeptBody::default();
+        let bodies = Bodies::new(&first);
+        let values = exprs
+            .iter()
+            .map(|expr| self.evaluate(&bodies, expr))
+            .collect::<Result<_, _>>();
+        Ok(values?)
+    }
+
+    /// Evaluates `expr` to completion, taking steps until no continuations
+    /// are left.
+    fn evaluate<'e>(
+        &mut self,
+        bodies: &'e Bodies<'e>,
+        expr: &'e Expr,
+    ) -> Result<Value, ControlFlow> {
+        let mut stack = Stack {
+            continuations: Vec::default(),
+            bodies,
+            calls: 0,
+        };
+        let mut step = Step::Eval(expr);
+        loop {
+            step = match step {
+                Step::Eval(expr) => self.enter(expr, false, &mut stack),
+                Step::EvalTail(expr) => self.enter(expr, true, &mut stack),
+                Step::Call(func, args, frame) => self
+                    .call(func, args, frame, &mut stack)
+                    .unwrap_or_else(Step::failed),
+                Step::Done(res) => match stack.continuations.pop() {
+                    Some(continuation) => self.resume(continuation, res, &mut stack),
+                    None => return res,
+                },
+                Step::TailCall(func, args) => self.tail_call(func, args, &mut stack),
+            }
+        }
+    }
+
+    fn child_environment(&self) -> Environment {
+        let visible = self.environment.borrow().declared();
+        Environment::nested(self.environment.clone(), visible)
+    }
+
+    /// Makes `env` the current scope, returning the one it replaces.
+    fn replace_environment(&mut self, env: Environment) -> Rc<RefCell<Environment>> {
+        mem::replace(&mut self.environment, Rc::new(RefCell::new(env)))
+    }
+
+    /// Starts on `expr`, covering it and, if tracing, logging it indented by
+    /// how deeply it is nested.
+    fn enter<'e>(&mut self, expr: &'e Expr, tail: bool, stack: &mut Stack<'e>) -> Step<'e> {
+        self.cover(expr);
+        if self.trace.is_some() {
+            let indent = "  ".repeat(self.depth);
+            self.write_trace(format_args!("{indent}{}", describe(expr)));
+            stack.push(Continuation::Leave(indent));
+            self.depth += 1;
+        }
+        self.interpret_expr(expr, tail, stack)
+            .unwrap_or_else(Step::failed)
+    }
+
+    /// Logs the value of a traced expression, or how it exited early.
+    fn write_trace_result(&mut self, indent: &str, res: Result<&Value, &ControlFlow>) {
+        match res {
+            Ok(value) => self.write_trace(format_args!("{indent}=> {value}")),
+            Err(ControlFlow::Error(err)) => self.write_trace(format_args!("{indent}!! {err}")),
+            Err(ControlFlow::Throw(value)) => {
+                self.write_trace(format_args!("{indent}!! throw {value}"))
+            }
+            Err(ControlFlow::Return(value)) => {
+                self.write_trace(format_args!("{indent}<- return {value}"))
+            }
+            Err(ControlFlow::Break(_)) => self.write_trace(format_args!("{indent}<- break")),
+            Err(ControlFlow::Continue(_)) => self.write_trace(format_args!("{indent}<- continue")),
+        }
+    }
+
+    /// Marks the line `expr` starts on as run.
+    fn cover(&mut self, expr: &Expr) {
+        if let Some(coverage) = &mut self.coverage {
+            if let Some(location) = expr.location() {
+                coverage.insert(location.line, true);
+            }
+        }
+    }
+
+    fn write_trace(&mut self, line: fmt::Arguments) {
+        if let Some(trace) = &mut self.trace {
+            writeln!(trace, "{line}").expect("Failed to write trace");
+        }
+    }
+
+    /// Takes the first step in evaluating `expr`: its value if it has no
+    /// operands, and otherwise the first operand to evaluate, with what to do
+    /// next on the stack.
+    fn interpret_expr<'e>(
+        &mut self,
+        expr: &'e Expr,
+        tail: bool,
+        stack: &mut Stack<'e>,
+    ) -> Result<Step<'e>, ControlFlow> {
+        let value = match expr {
             Expr::Literal(value) => value.clone(),
-            Expr::Group(e) => self.interpret_expr(e),
-            Expr::Binary(lhs, op, rhs) => self.interpret_binary(lhs, op, rhs),
-            Expr::Unary(op, e) => self.interpret_unary(op, e),
-            Expr::Declaration(lhs, init) => self.interpret_decl(lhs, init),
-            Expr::Assignment(lhs, value) => self.interpret_assignment(lhs, value),
-            Expr::While(cond, body) => self.interpret_while(cond, body),
-            Expr::Block(exprs) => {
-                let new_env = Environment {
-                    parent: Some(self.environment.clone()),
-                    ..Default::default()
+            Expr::Group(e) => return Ok(Step::eval(e, tail)),
+            Expr::Propagate(e) => return Ok(stack.then(Continuation::Propagate, e)),
+            Expr::Tuple(elements) => {
+                return self.interpret_elements(elements, Vec::default(), Collection::Tuple, stack)
+            }
+            Expr::List(elements) => {
+                return self.interpret_elements(elements, Vec::default(), Collection::List, stack)
+            }
+            Expr::Set(elements) => {
+                return self.interpret_elements(elements, Vec::default(), Collection::Set, stack)
+            }
+            Expr::Dict(entries) => match entries.first() {
+                Some((key, _)) => {
+                    return Ok(stack.then(Continuation::Dict(entries, None, Vec::default()), key))
+                }
+                None => Value::Dict(Rc::from([])),
+            },
+            Expr::Binary(lhs, op, rhs) => {
+                let continuation = match op.token_type {
+                    TokenType::AndAnd | TokenType::OrOr => Continuation::Logical(op, rhs),
+                    _ => match self.immediate(lhs) {
+                        Some(lhs) => return self.interpret_operator(op, lhs?, rhs, stack),
+                        None => Continuation::Binary(op, rhs),
+                    },
                 };
-                let old_env = self.environment.clone();
-                self.environment = Rc::new(RefCell::new(new_env));
-                self.interpret(exprs.clone());
-                self.environment = old_env;
-                Value::Bool(false)
+                return Ok(stack.then(continuation, lhs));
+            }
+            Expr::Unary(op, e) => return Ok(stack.then(Continuation::Unary(op), e)),
+            Expr::Chain(first, comparisons) => {
+                return Ok(stack.then(Continuation::Chain(None, comparisons), first))
+            }
+            Expr::Declaration(lhs, _, init) => {
+                let init = init
+                    .as_ref()
+                    .expect("TODO: declarations must have initial value");
+                return Ok(stack.then(Continuation::Declare(lhs), init));
+            }
+            Expr::Assignment(lhs, value, location) => {
+                return Ok(stack.then(Continuation::Assign(lhs, *location), value))
+            }
+            Expr::While(..) | Expr::Repeat(..) | Expr::Loop(_) => {
+                return Ok(self.interpret_loop(expr, None, stack))
+            }
+            Expr::Labeled(label, inner) => {
+                return Ok(self.interpret_loop(inner, Some(label.as_str()), stack))
+            }
+            Expr::Collect(cond, body) => {
+                return Ok(stack.then(
+                    Continuation::CollectCondition(cond, body, Vec::default()),
+                    cond,
+                ))
+            }
+            Expr::Comprehension(_, _, iterable, _) => {
+                return Ok(stack.then(Continuation::Iterable(expr), iterable))
+            }
+            Expr::Try(body, name, handler) => {
+                return Ok(stack.then(Continuation::Try(name, handler), body))
+            }
+            Expr::Import(path) => return self.interpret_module(path, true, stack),
+            Expr::Module(path) => return self.interpret_module(path, false, stack),
+            Expr::Return(e) if tail => return Ok(Step::EvalTail(e)),
+            Expr::Return(e) => return Ok(stack.then(Continuation::Return, e)),
+            Expr::Break(label) => return Err(ControlFlow::Break(label.clone())),
+            Expr::Continue(label) => return Err(ControlFlow::Continue(label.clone())),
+            Expr::Block(exprs) => {
+                let scope = self.replace_environment(self.child_environment());
+                stack.push(Continuation::Unit);
+                stack.push(Continuation::Scope(scope));
+                return Ok(self.interpret_sequence(exprs, false, stack));
             }
             Expr::If(cond, true_branch, false_branch) => {
-                self.interpret_if(cond, true_branch, false_branch)
+                let continuation = Continuation::If(true_branch, false_branch.as_deref(), tail);
+                return Ok(stack.then(continuation, cond));
+            }
+            Expr::Guard(cond, else_branch) => {
+                return Ok(stack.then(Continuation::Guard(else_branch), cond))
+            }
+            Expr::Match(scrutinee, arms) => {
+                return Ok(stack.then(Continuation::Match(arms), scrutinee))
+            }
+            Expr::Switch(scrutinee, cases, default) => {
+                let continuation = Continuation::Switch(cases, default.as_deref());
+                return Ok(stack.then(continuation, scrutinee));
+            }
+            Expr::FunctionCall(callee, args, location) => {
+                let call = CallSite {
+                    callee,
+                    args,
+                    location: *location,
+                    tail,
+                };
+                // Names are resolved when the call happens, so top-level
+                // functions may call functions declared after them.
+                return match callee.as_ref() {
+                    Expr::Identifier(name, location) => {
+                        let func = self.lookup(name).ok_or_else(|| {
+                            RuntimeError::UndefinedFunction(name.clone()).at(*location)
+                        })?;
+                        self.interpret_arguments(func, Vec::default(), call, stack)
+                    }
+                    Expr::Get(obj, _) => Ok(stack.then(Continuation::Method(call), obj)),
+                    callee => Ok(stack.then(Continuation::Callee(call), callee)),
+                };
+            }
+            Expr::Lambda(params, body, _) => Value::Lambda(
+                params.clone(),
+                body.clone(),
+                self.environment.clone(),
+                self.environment.borrow().declared(),
+            ),
+            Expr::CaptureByValue(captures, lambda) => {
+                let mut snapshot = self.child_environment();
+                for name in captures {
+                    let value = self
+                        .environment
+                        .borrow()
+                        .get(name)
+                        .ok_or_else(|| RuntimeError::UndefinedVariable(name.clone()))?;
+                    snapshot.define(name, value);
+                }
+
+                let scope = self.replace_environment(snapshot);
+                return Ok(stack.then(Continuation::Scope(scope), lambda));
+            }
+            Expr::Get(obj, name) => return Ok(stack.then(Continuation::Get(name), obj)),
+            Expr::BuiltinFunction(token) => match token.token_type {
+                TokenType::Print => Value::Builtin(String::from("print")),
+                TokenType::Assert => Value::Builtin(String::from("assert")),
+                TokenType::AssertEq => Value::Builtin(String::from("assert_eq")),
+                _ => panic!("Unknown builtin {token:?}"),
+            },
+            Expr::Identifier(identifier, location) => self
+                .lookup(identifier)
+                .ok_or_else(|| RuntimeError::UndefinedVariable(identifier.clone()).at(*location))?,
+        };
+        Ok(Step::Done(Ok(value)))
+    }
+
+    /// Carries on with `continuation` now that the expression it was waiting
+    /// on has exited with `res`. Most only act on a value, and pass early
+    /// exits on to the next continuation.
+    fn resume<'e>(
+        &mut self,
+        continuation: Continuation<'e>,
+        res: Result<Value, ControlFlow>,
+        stack: &mut Stack<'e>,
+    ) -> Step<'e> {
+        let res = match continuation {
+            Continuation::Leave(indent) => {
+                self.depth -= 1;
+                self.write_trace_result(&indent, res.as_ref());
+                res.map(|value| Step::Done(Ok(value)))
+            }
+            Continuation::Scope(scope) => {
+                self.environment = scope;
+                res.map(|value| Step::Done(Ok(value)))
+            }
+            Continuation::Function { scope, traced } => {
+                stack.calls -= 1;
+                Ok(Step::Done(self.leave_function(scope, traced, res)))
+            }
+            Continuation::Module {
+                path,
+                scope,
+                coverage,
+                import,
+            } => Ok(Step::Done(
+                self.leave_module(path, scope, coverage, import, res),
+            )),
+            Continuation::Try(name, handler) => match res {
+                Err(ControlFlow::Throw(value)) => {
+                    let mut handler_env = self.child_environment();
+                    handler_env.define(name, value);
+                    let scope = self.replace_environment(handler_env);
+                    Ok(stack.then(Continuation::Scope(scope), handler))
+                }
+                res => res.map(|value| Step::Done(Ok(value))),
+            },
+            Continuation::MatchGuard(_, _, scope) if res.is_err() => {
+                self.environment = scope;
+                res.map(|value| Step::Done(Ok(value)))
+            }
+            Continuation::Comprehension(comprehension, _) if res.is_err() => {
+                self.environment = comprehension.scope;
+                res.map(|value| Step::Done(Ok(value)))
+            }
+            Continuation::LoopBody(expr, label) => {
+                let aimed_here =
+                    |target: &Option<String>| target.is_none() || target.as_deref() == label;
+                match res {
+                    Ok(_) => Ok(self.continue_loop(expr, label, stack)),
+                    Err(ControlFlow::Continue(target)) if aimed_here(&target) => {
+                        Ok(self.continue_loop(expr, label, stack))
+                    }
+                    Err(ControlFlow::Break(target)) if aimed_here(&target) => {
+                        Ok(Step::Done(Ok(Value::Unit)))
+                    }
+                    Err(flow) => Err(flow),
+                }
+            }
+            // A pass cut short by `continue` adds nothing, and `break` ends
+            // the loop with the values so far.
+            Continuation::CollectBody(cond, body, mut values) => match res {
+                Ok(value) => {
+                    values.push(value);
+                    Ok(stack.then(Continuation::CollectCondition(cond, body, values), cond))
+                }
+                Err(ControlFlow::Continue(None)) => {
+                    Ok(stack.then(Continuation::CollectCondition(cond, body, values), cond))
+                }
+                Err(ControlFlow::Break(None)) => Ok(Step::Done(Ok(Value::List(values.into())))),
+                Err(flow) => Err(flow),
+            },
+            continuation => res.and_then(|value| self.resume_value(continuation, value, stack)),
+        };
+        res.unwrap_or_else(Step::failed)
+    }
+
+    /// Carries on with `continuation` now that the expression it was waiting
+    /// on has evaluated to `value`.
+    fn resume_value<'e>(
+        &mut self,
+        continuation: Continuation<'e>,
+        value: Value,
+        stack: &mut Stack<'e>,
+    ) -> Result<Step<'e>, ControlFlow> {
+        let value = match continuation {
+            Continuation::Unit => Value::Unit,
+            Continuation::Sequence(exprs, tail) => {
+                return Ok(self.interpret_sequence(exprs, tail, stack))
+            }
+            Continuation::Elements(exprs, mut values, collection) => {
+                values.push(value);
+                return self.interpret_elements(exprs, values, collection, stack);
+            }
+            Continuation::Dict(entries, None, dict) => {
+                value.check_hashable()?;
+                let continuation = Continuation::Dict(entries, Some(value), dict);
+                return Ok(stack.then(continuation, &entries[0].1));
+            }
+            Continuation::Dict(entries, Some(key), mut dict) => {
+                match dict.iter_mut().find(|(entry_key, _)| *entry_key == key) {
+                    Some(entry) => entry.1 = value,
+                    None => dict.push((key, value)),
+                }
+                match &entries[1..] {
+                    [] => Value::Dict(dict.into()),
+                    entries => {
+                        let continuation = Continuation::Dict(entries, None, dict);
+                        return Ok(stack.then(continuation, &entries[0].0));
+                    }
+                }
+            }
+            Continuation::Propagate => match value {
+                Value::Optional(Some(value)) => *value,
+                Value::Optional(None) => return Err(ControlFlow::Return(Value::Optional(None))),
+                value => {
+                    return Err(RuntimeError::TypeError(format!(
+                        "Cannot apply ? to {}",
+                        value.type_name()
+                    ))
+                    .into())
+                }
+            },
+            Continuation::Return => return Err(ControlFlow::Return(value)),
+            Continuation::Unary(op) => apply_unary(op, value)?,
+            Continuation::Binary(op, rhs) => return self.interpret_operator(op, value, rhs, stack),
+            Continuation::Operator(op, lhs) => {
+                apply_operator(&op.token_type, lhs, value).map_err(|err| err.at(op.location))?
+            }
+            // The right operand is skipped when the left one already decides
+            // the result.
+            Continuation::Logical(op, rhs) => {
+                if value == Value::Bool(op.token_type == TokenType::OrOr) {
+                    value
+                } else {
+                    return Ok(stack.then(Continuation::Operator(op, value), rhs));
+                }
+            }
+            Continuation::Chain(None, comparisons) => {
+                let continuation = Continuation::Chain(Some(value), comparisons);
+                return Ok(stack.then(continuation, &comparisons[0].1));
+            }
+            // Each operand is evaluated once, as the right of one comparison
+            // is the left of the next, and the chain stops at the first that
+            // is false.
+            Continuation::Chain(Some(lhs), comparisons) => {
+                let op = &comparisons[0].0;
+                let holds = apply_operator(&op.token_type, lhs, value.clone())
+                    .map_err(|err| err.at(op.location))?;
+                match &comparisons[1..] {
+                    _ if holds != Value::Bool(true) => holds,
+                    [] => holds,
+                    comparisons => {
+                        let continuation = Continuation::Chain(Some(value), comparisons);
+                        return Ok(stack.then(continuation, &comparisons[0].1));
+                    }
+                }
+            }
+            Continuation::Declare(lhs) => self.interpret_decl(lhs, value)?,
+            Continuation::Assign(target, location) => {
+                return self.assign(target, value.clone(), location, value, stack)
+            }
+            Continuation::AssignField {
+                obj,
+                name,
+                value: field,
+                location,
+                result,
+            } => {
+                let res = match value {
+                    Value::Module(module) => Environment::set_member(&module, name, field),
+                    Value::Dict(entries) => {
+                        let mut entries = entries.to_vec();
+                        let key = Value::String(name.into());
+                        match entries.iter_mut().find(|(entry_key, _)| *entry_key == key) {
+                            Some((_, entry)) => *entry = field,
+                            None => entries.push((key, field)),
+                        }
+                        let dict = Value::Dict(entries.into());
+                        return self.assign(obj, dict, location, result, stack);
+                    }
+                    obj => Err(RuntimeError::TypeError(format!(
+                        "Cannot assign field {name:?} of {}",
+                        obj.type_name()
+                    ))),
+                };
+                res.map_err(|err| err.at(location))?;
+                result
+            }
+            Continuation::Get(name) => get_field(value, name)?,
+            Continuation::If(true_branch, false_branch, tail) => {
+                return Ok(match (value, false_branch) {
+                    (Value::Bool(true), _) => Step::eval(true_branch, tail),
+                    (_, Some(false_branch)) => Step::eval(false_branch, tail),
+                    (_, None) => Step::Done(Ok(Value::Unit)),
+                })
+            }
+            Continuation::Guard(else_branch) if value != Value::Bool(true) => {
+                return Ok(stack.then(Continuation::GuardElse, else_branch))
+            }
+            Continuation::Guard(_) => Value::Unit,
+            Continuation::GuardElse => return Err(RuntimeError::GuardFellThrough.into()),
+            Continuation::Callee(call) => {
+                return self.interpret_arguments(value, Vec::default(), call, stack)
+            }
+            // `obj.name! args` calls the member of a module, and for any other
+            // value is `name! obj args`.
+            Continuation::Method(call) => {
+                let Expr::Get(_, name) = call.callee else {
+                    unreachable!("Methods are called through a field")
+                };
+                return match value {
+                    Value::Module(module) => {
+                        let func = Environment::member(&module, name)?;
+                        self.interpret_arguments(func, Vec::default(), call, stack)
+                    }
+                    obj => {
+                        let func = self
+                            .lookup(name)
+                            .ok_or_else(|| RuntimeError::UndefinedFunction(name.clone()))?;
+                        self.interpret_arguments(func, vec![obj], call, stack)
+                    }
+                };
+            }
+            Continuation::Match(arms) => return self.interpret_arms(value, arms, stack),
+            Continuation::MatchGuard(scrutinee, arms, scope) => {
+                if value == Value::Bool(true) {
+                    return Ok(stack.then(Continuation::Scope(scope), &arms[0].2));
+                }
+                self.environment = scope;
+                return self.interpret_arms(scrutinee, &arms[1..], stack);
             }
-            Expr::FunctionCall(id, args) => self.interpret_call(id, args),
-            Expr::Lambda(params, body) => {
-                Value::Lambda(params.clone(), body.clone(), self.environment.clone())
+            Continuation::Switch(cases, default) => {
+                return Ok(self.interpret_cases(value, cases, default, stack))
             }
-            Expr::Get(_, _) => todo!(),
-            Expr::BuiltinFunction(_) => todo!(),
-            Expr::Identifier(identifier) => self
-                .environment
-                .borrow()
-                .get(&identifier)
-                .unwrap_or_else(|| panic!("Undefined Variable {identifier:?}"))
-                .clone(),
+            // Cases never fall through.
+            Continuation::SwitchCase(scrutinee, cases, default) => {
+                if value == scrutinee {
+                    return Ok(Step::Eval(&cases[0].1));
+                }
+                return Ok(self.interpret_cases(scrutinee, &cases[1..], default, stack));
+            }
+            Continuation::LoopCondition(expr, label) => {
+                let holds = value == Value::Bool(true);
+                return Ok(match expr {
+                    Expr::While(_, body, _) | Expr::Repeat(body, _)
+                        if holds == matches!(expr, Expr::While(..)) =>
+                    {
+                        stack.then(Continuation::LoopBody(expr, label), body)
+                    }
+                    Expr::While(_, _, Some(else_branch)) => {
+                        stack.then(Continuation::Unit, else_branch)
+                    }
+                    _ => Step::Done(Ok(Value::Unit)),
+                });
+            }
+            Continuation::CollectCondition(cond, body, values) => {
+                if value != Value::Bool(true) {
+                    return Ok(Step::Done(Ok(Value::List(values.into()))));
+                }
+                stack.push(Continuation::CollectBody(cond, body, values));
+                let Expr::Block(exprs) = body else {
+                    return Ok(Step::Eval(body));
+                };
+                let scope = self.replace_environment(self.child_environment());
+                stack.push(Continuation::Scope(scope));
+                return Ok(self.interpret_sequence(exprs, false, stack));
+            }
+            Continuation::Iterable(expr) => {
+                let Expr::Comprehension(body, pattern, _, guard) = expr else {
+                    unreachable!("Only comprehensions have iterables")
+                };
+                let elements = match value {
+                    Value::List(elements) => elements.to_vec(),
+                    Value::Set(elements) | Value::Tuple(elements) => elements.to_vec(),
+                    value => {
+                        return Err(RuntimeError::TypeError(format!(
+                            "Cannot iterate over {}",
+                            value.type_name()
+                        ))
+                        .into())
+                    }
+                };
+                let comprehension = Comprehension {
+                    body,
+                    pattern,
+                    guard: guard.as_deref(),
+                    elements: elements.into_iter(),
+                    values: Vec::default(),
+                    scope: self.environment.clone(),
+                };
+                return self.interpret_comprehension(Box::new(comprehension), stack);
+            }
+            Continuation::Comprehension(comprehension, true) if value == Value::Bool(true) => {
+                let body = comprehension.body;
+                return Ok(stack.then(Continuation::Comprehension(comprehension, false), body));
+            }
+            Continuation::Comprehension(mut comprehension, guarded) => {
+                self.environment = comprehension.scope.clone();
+                if !guarded {
+                    comprehension.values.push(value);
+                }
+                return self.interpret_comprehension(comprehension, stack);
+            }
+            Continuation::Iteration(iteration) => return self.iterate(iteration, value, stack),
+            Continuation::Memoize(native, key) => {
+                if let Native::Memoize(_, cache) = native.as_ref() {
+                    cache.borrow_mut().insert(key, value.clone());
+                }
+                value
+            }
+            Continuation::Force(result) => {
+                *result.borrow_mut() = Some(value.clone());
+                value
+            }
+            Continuation::Leave(_)
+            | Continuation::Scope(_)
+            | Continuation::Function { .. }
+            | Continuation::Module { .. }
+            | Continuation::Try(..)
+            | Continuation::LoopBody(..)
+            | Continuation::CollectBody(..) => unreachable!("Handled however the expression exits"),
+        };
+        Ok(Step::Done(Ok(value)))
+    }
+
+    /// The value of `expr` if it's a name or literal and isn't traced, so can
+    /// be read in place rather than through the stack.
+    fn immediate(&mut self, expr: &Expr) -> Option<Result<Value, ControlFlow>> {
+        if self.trace.is_some() {
+            return None;
         }
+        let value = match expr {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Identifier(identifier, location) => self.lookup(identifier).ok_or_else(|| {
+                RuntimeError::UndefinedVariable(identifier.clone())
+                    .at(*location)
+                    .into()
+            }),
+            _ => return None,
+        };
+        self.cover(expr);
+        Some(value)
     }
 
-    fn interpret_call(&mut self, id: &Expr, args: &Vec<Expr>) -> Value {
-        if let Expr::BuiltinFunction(builtin) = id {
-            return self.interpret_builtin(builtin, args);
+    /// Applies a binary operator once its left operand has been evaluated,
+    /// reading the right operand in place where it can.
+    fn interpret_operator<'e>(
+        &mut self,
+        op: &'e Token,
+        lhs: Value,
+        rhs: &'e Expr,
+        stack: &mut Stack<'e>,
+    ) -> Result<Step<'e>, ControlFlow> {
+        match self.immediate(rhs) {
+            Some(rhs) => {
+                let value =
+                    apply_operator(&op.token_type, lhs, rhs?).map_err(|err| err.at(op.location))?;
+                Ok(Step::Done(Ok(value)))
+            }
+            None => Ok(stack.then(Continuation::Operator(op, lhs), rhs)),
         }
+    }
 
-        let func = self.interpret_expr(id);
+    /// Evaluates `exprs` in order, the last in tail position if `tail`. The
+    /// value of the sequence is that of the last expression, or unit if there
+    /// are none.
+    fn interpret_sequence<'e>(
+        &mut self,
+        exprs: &'e [Expr],
+        tail: bool,
+        stack: &mut Stack<'e>,
+    ) -> Step<'e> {
+        match exprs {
+            [] => Step::Done(Ok(Value::Unit)),
+            [last] => Step::eval(last, tail),
+            [first, rest @ ..] => stack.then(Continuation::Sequence(rest, tail), first),
+        }
+    }
 
-        let Value::Lambda(params, body, closure) = func else {
-            panic!("Error: Not a function")
-        };
+    /// Evaluates `exprs` in order after the `values` of those before them,
+    /// then gathers all the values into `collection`.
+    fn interpret_elements<'e>(
+        &mut self,
+        mut exprs: &'e [Expr],
+        mut values: Vec<Value>,
+        collection: Collection<'e>,
+        stack: &mut Stack<'e>,
+    ) -> Result<Step<'e>, ControlFlow> {
+        while let [first, rest @ ..] = exprs {
+            match self.immediate(first) {
+                Some(value) => values.push(value?),
+                None => {
+                    return Ok(stack.then(Continuation::Elements(rest, values, collection), first))
+                }
+            }
+            exprs = rest;
+        }
 
-        let mut new_env = Environment {
-            parent: Some(closure),
-            ..Default::default()
+        let value = match collection {
+            Collection::Tuple => Value::Tuple(values.into()),
+            Collection::List => Value::List(values.into()),
+            Collection::Set => {
+                let mut set: Vec<Value> = Vec::default();
+                for value in values {
+                    value.check_hashable()?;
+                    if !set.contains(&value) {
+                        set.push(value);
+                    }
+                }
+                Value::Set(set.into())
+            }
+            // Only calls to lambdas are deferred, so builtins such as
+            // `dump_env!` still run in the caller's own scope.
+            Collection::Arguments(func @ Value::Lambda(..), call) if call.tail => {
+                return Ok(Step::TailCall(func, values))
+            }
+            Collection::Arguments(func @ Value::Lambda(..), call) => {
+                let function = match call.callee {
+                    Expr::Identifier(name, _) | Expr::Get(_, name) => name.clone(),
+                    _ => String::from("<lambda>"),
+                };
+                let frame = Frame {
+                    function,
+                    line: call.location.line,
+                };
+                return Ok(Step::Call(func, values, Some(frame)));
+            }
+            Collection::Arguments(func, _) => return Ok(Step::Call(func, values, None)),
         };
+        Ok(Step::Done(Ok(value)))
+    }
 
-        params.iter().zip(args.iter()).for_each(|(param, arg)| {
-            let Expr::Identifier(param) = param.clone() else {
-                panic!("Invalid function parameter")
-            };
-            new_env.define(&param, self.interpret_expr(arg))
-        });
+    /// Evaluates the arguments of a call to `func`, after the `values` of any
+    /// passed implicitly.
+    fn interpret_arguments<'e>(
+        &mut self,
+        func: Value,
+        values: Vec<Value>,
+        call: CallSite<'e>,
+        stack: &mut Stack<'e>,
+    ) -> Result<Step<'e>, ControlFlow> {
+        if !matches!(
+            func,
+            Value::Lambda(..) | Value::Builtin(_) | Value::Native(_)
+        ) {
+            return Err(
+                RuntimeError::TypeError(format!("{} is not a function", func.type_name()))
+                    .at(call.location)
+                    .into(),
+            );
+        }
+
+        self.interpret_elements(call.args, values, Collection::Arguments(func, call), stack)
+    }
+
+    fn lookup(&self, identifier: &str) -> Option<Value> {
+        let value = self.environment.borrow().get(identifier);
+        value.or_else(|| is_builtin(identifier).then(|| Value::Builtin(identifier.to_string())))
+    }
+
+    /// Calls `func`, pushing `frame` for traces while a lambda runs.
+    fn call<'e>(
+        &mut self,
+        func: Value,
+        args: Vec<Value>,
+        frame: Option<Frame>,
+        stack: &mut Stack<'e>,
+    ) -> Result<Step<'e>, ControlFlow> {
+        match func {
+            Value::Lambda(params, body, closure, visible) => {
+                if stack.calls >= MAX_CALLS {
+                    return Err(RuntimeError::StackOverflow.into());
+                }
+                stack.calls += 1;
+                stack.push(Continuation::Function {
+                    scope: self.environment.clone(),
+                    traced: frame.is_some(),
+                });
+                self.frames.extend(frame);
+                self.enter_function(&params, &body, closure, visible, args, stack)
+            }
+            Value::Builtin(name) => self.call_builtin(&name, args, stack),
+            Value::Native(native) => self.call_native(&native, args, stack),
+            func => Err(
+                RuntimeError::TypeError(format!("{} is not a function", func.type_name())).into(),
+            ),
+        }
+    }
 
-        let old_env = self.environment.clone();
+    /// Binds the arguments of a lambda call in a new scope and starts on the
+    /// body. The scope to return to is already on the stack.
+    fn enter_function<'e>(
+        &mut self,
+        params: &[Expr],
+        body: &Rc<[Expr]>,
+        closure: Rc<RefCell<Environment>>,
+        visible: usize,
+        args: Vec<Value>,
+        stack: &mut Stack<'e>,
+    ) -> Result<Step<'e>, ControlFlow> {
+        if body.is_empty() {
+            panic!("TODO: Functions must have implicit return");
+        }
+
+        let mut new_env = Environment::nested(closure, visible);
+        for (param, arg) in params.iter().zip(args) {
+            new_env.define_pattern(param, arg)?;
+        }
         self.environment = Rc::new(RefCell::new(new_env));
+        Ok(self.interpret_sequence(stack.bodies.keep(body), true, stack))
+    }
 
-        let res = self
-            .interpret(body)
-            .pop()
-            .expect("TODO: Functions must have implicit return");
+    /// Unwinds the expressions a call in tail position is nested in, then
+    /// makes the call in place of the function they're in. A deferred call is
+    /// logged as leaving for the call, whose body is traced next.
+    fn tail_call<'e>(&mut self, func: Value, args: Vec<Value>, stack: &mut Stack<'e>) -> Step<'e> {
+        loop {
+            match stack.continuations.pop() {
+                Some(Continuation::Leave(indent)) => {
+                    self.depth -= 1;
+                    self.write_trace(format_args!("{indent}<- tail call"));
+                }
+                Some(function @ Continuation::Function { .. }) => {
+                    stack.push(function);
+                    let Value::Lambda(params, body, closure, visible) = func else {
+                        unreachable!("Only lambda calls are deferred")
+                    };
+                    return self
+                        .enter_function(&params, &body, closure, visible, args, stack)
+                        .unwrap_or_else(Step::failed);
+                }
+                _ => unreachable!("Only function bodies have expressions in tail position"),
+            }
+        }
+    }
 
-        self.environment = old_env;
+    /// Returns from a lambda call to `scope`, with the function's value. An
+    /// error leaving a traced call gets the calls active when it happened.
+    fn leave_function(
+        &mut self,
+        scope: Rc<RefCell<Environment>>,
+        traced: bool,
+        res: Result<Value, ControlFlow>,
+    ) -> Result<Value, ControlFlow> {
+        self.environment = scope;
+        let res = match res {
+            Ok(value) | Err(ControlFlow::Return(value)) => Ok(value),
+            Err(ControlFlow::Break(_) | ControlFlow::Continue(_)) => {
+                Err(RuntimeError::OutsideLoop.into())
+            }
+            Err(flow) => Err(flow),
+        };
+        if !traced {
+            return res;
+        }
 
+        let res = res.map_err(|flow| match flow {
+            // A trace of a stack overflow would only repeat the same call.
+            ControlFlow::Error(
+                error @ (RuntimeError::Traced { .. } | RuntimeError::StackOverflow),
+            ) => ControlFlow::Error(error),
+            ControlFlow::Error(error) => ControlFlow::Error(RuntimeError::Traced {
+                error: Box::new(error),
+                trace: self.frames.clone(),
+            }),
+            flow => flow,
+        });
+        self.frames.pop();
         res
     }
 
-    fn interpret_assignment(&mut self, lhs: &Expr, value: &Expr) -> Value {
-        let Expr::Identifier(identifier) = lhs else {
-            panic!("Invalid LHS of assignment")
+    /// Stores `value` in a variable, or in a field of one, for the assignment
+    /// at `location` whose value is `result`. Dicts are values, so setting a
+    /// field rebuilds each dict on the way down and assigns the result back
+    /// to the variable; modules are updated in place.
+    fn assign<'e>(
+        &mut self,
+        target: &'e Expr,
+        value: Value,
+        location: Location,
+        result: Value,
+        stack: &mut Stack<'e>,
+    ) -> Result<Step<'e>, ControlFlow> {
+        let res = match target {
+            Expr::Identifier(identifier, _) => self.environment.borrow_mut().set(identifier, value),
+            Expr::Get(obj, name) => {
+                let continuation = Continuation::AssignField {
+                    obj,
+                    name,
+                    value,
+                    location,
+                    result,
+                };
+                return Ok(stack.then(continuation, obj));
+            }
+            // The parser only builds assignments to names and fields.
+            target => Err(RuntimeError::TypeError(format!(
+                "Invalid LHS of assignment {}",
+                describe(target)
+            ))),
+        };
+        res.map_err(|err| err.at(location))?;
+        Ok(Step::Done(Ok(result)))
+    }
+
+    /// Calls the builtin `name`. Those that call a function on each element
+    /// of a list make the calls through the stack, like any other call.
+    fn call_builtin<'e>(
+        &mut self,
+        name: &str,
+        args: Vec<Value>,
+        stack: &mut Stack<'e>,
+    ) -> Result<Step<'e>, ControlFlow> {
+        let (func, elements, fold) = match (name, args.as_slice()) {
+            ("each", [func, Value::List(elements)]) => (func, elements.clone(), Fold::Each),
+            ("each", [func, Value::Set(elements) | Value::Tuple(elements)]) => {
+                (func, elements.to_vec().into(), Fold::Each)
+            }
+            ("each", [func, Value::Dict(entries)]) => {
+                let entries = entries
+                    .iter()
+                    .map(|(key, value)| Value::Tuple([key.clone(), value.clone()].into()))
+                    .collect();
+                (func, entries, Fold::Each)
+            }
+            ("take_while", [pred, Value::List(elements)]) => {
+                (pred, elements.clone(), Fold::TakeWhile)
+            }
+            ("drop_while", [pred, Value::List(elements)]) => {
+                (pred, elements.clone(), Fold::DropWhile)
+            }
+            ("flat_map", [func, Value::List(elements)]) => {
+                (func, elements.clone(), Fold::FlatMap(Vec::default()))
+            }
+            ("group_by", [key_fn, Value::List(elements)]) => {
+                (key_fn, elements.clone(), Fold::GroupBy(Vec::default()))
+            }
+            ("count", [pred, Value::List(elements)]) => (pred, elements.clone(), Fold::Count(0)),
+            // Both stop calling the predicate once the answer is known.
+            ("all", [pred, Value::List(elements)]) => (pred, elements.clone(), Fold::All),
+            ("any", [pred, Value::List(elements)]) => (pred, elements.clone(), Fold::Any),
+            ("reduce", [func, init, Value::List(elements)]) => {
+                (func, elements.clone(), Fold::Reduce(init.clone()))
+            }
+            // Every accumulator `reduce!` passes through, starting with `init`,
+            // so the result is one longer than the list.
+            ("scan", [func, init, Value::List(elements)]) => {
+                (func, elements.clone(), Fold::Scan(vec![init.clone()]))
+            }
+            // Lambdas don't otherwise check how many arguments they get, but
+            // a list of the wrong length is almost certainly a mistake.
+            ("apply", [Value::Lambda(params, ..), Value::List(elements)])
+                if params.len() != elements.len() =>
+            {
+                return Err(RuntimeError::TypeError(format!(
+                    "Lambda takes {} arguments but was given {}",
+                    params.len(),
+                    elements.len()
+                ))
+                .into())
+            }
+            ("apply", [func, Value::List(elements)]) => {
+                return Ok(Step::Call(func.clone(), elements.to_vec(), None))
+            }
+            ("force", [Value::Thunk(func, result)]) => {
+                // Not borrowed during the call, which may force other copies.
+                let cached = result.borrow().clone();
+                return Ok(match cached {
+                    Some(value) => Step::Done(Ok(value)),
+                    None => {
+                        stack.push(Continuation::Force(result.clone()));
+                        Step::Call(*func.clone(), vec![], None)
+                    }
+                });
+            }
+            _ => return Ok(Step::Done(self.interpret_builtin(name, args))),
+        };
+
+        let iteration = Iteration {
+            name: name.to_string(),
+            func: func.clone(),
+            elements,
+            index: 0,
+            fold,
         };
+        Ok(self.next_element(Box::new(iteration), stack))
+    }
 
-        let value = self.interpret_expr(value);
-        self.environment.borrow_mut().set(identifier, value.clone());
+    /// Calls the function of a builtin iterating over a list on the element
+    /// at `index`, or finishes once there are no more.
+    fn next_element<'e>(&mut self, iteration: Box<Iteration>, stack: &mut Stack<'e>) -> Step<'e> {
+        if iteration.index == iteration.elements.len() {
+            return Step::Done(Ok(iteration.finish()));
+        }
+        let (func, args) = (iteration.func.clone(), iteration.args());
+        stack.push(Continuation::Iteration(iteration));
+        Step::Call(func, args, None)
+    }
 
-        value
+    /// Takes what the function of a builtin iterating over a list returned
+    /// for the element at `index`, and goes on to the next element.
+    fn iterate<'e>(
+        &mut self,
+        mut iteration: Box<Iteration>,
+        value: Value,
+        stack: &mut Stack<'e>,
+    ) -> Result<Step<'e>, ControlFlow> {
+        let element = &iteration.elements[iteration.index];
+        match &mut iteration.fold {
+            Fold::Each => {}
+            Fold::TakeWhile | Fold::DropWhile => {
+                if !predicate(&iteration.name, value)? {
+                    return Ok(Step::Done(Ok(iteration.finish())));
+                }
+            }
+            Fold::Count(count) => *count += predicate(&iteration.name, value)? as i32,
+            Fold::All => {
+                if !predicate(&iteration.name, value)? {
+                    return Ok(Step::Done(Ok(Value::Bool(false))));
+                }
+            }
+            Fold::Any => {
+                if predicate(&iteration.name, value)? {
+                    return Ok(Step::Done(Ok(Value::Bool(true))));
+                }
+            }
+            Fold::FlatMap(flattened) => flattened.extend(flatten_element(&iteration.name, value)?),
+            Fold::GroupBy(groups) => {
+                value.check_hashable()?;
+                match groups.iter_mut().find(|(group_key, _)| *group_key == value) {
+                    Some((_, group)) => group.push(element.clone()),
+                    None => groups.push((value, vec![element.clone()])),
+                }
+            }
+            Fold::Reduce(total) => *total = value,
+            Fold::Scan(totals) => totals.push(value),
+        }
+        iteration.index += 1;
+        Ok(self.next_element(iteration, stack))
     }
 
-    fn interpret_builtin(&mut self, token: &Token, args: &Vec<Expr>) -> Value {
-        let args = self.interpret(args.clone());
+    fn interpret_builtin(&mut self, name: &str, args: Vec<Value>) -> Result<Value, ControlFlow> {
+        let operator = OPERATORS.iter().find(|(operator, _)| *operator == name);
+        if let (Some((_, op)), [lhs, rhs]) = (operator, args.as_slice()) {
+            return Ok(apply_operator(op, lhs.clone(), rhs.clone())?);
+        }
 
-        match token.token_type {
-            TokenType::Print => writeln!(self.output, "{args:?}").expect("Failed to write output"),
-            _ => panic!("Unknown builtin {token:?}"),
+        let value = match (name, args.as_slice()) {
+            ("print", _) => {
+                writeln!(self.output, "{args:?}").expect("Failed to write output");
+                Value::Unit
+            }
+            // The Debug form of each argument, for troubleshooting.
+            ("debug", _) => {
+                let line = args
+                    .iter()
+                    .map(|value| format!("{value:?}"))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                writeln!(self.output, "{line}").expect("Failed to write output");
+                Value::Unit
+            }
+            // Every variable in scope, innermost scope first, so a shadowed
+            // name shows up before whatever it shadows. Scopes with nothing
+            // visible in them are left out.
+            ("dump_env", []) => {
+                let mut scope = Some(self.environment.clone());
+                let mut visible = usize::MAX;
+                let mut depth = 0;
+                while let Some(environment) = scope {
+                    let environment = environment.borrow();
+                    let is_global = environment.parent.is_none();
+                    let mut names = environment
+                        .values
+                        .iter()
+                        .filter(|(_, (index, _))| is_global || *index < visible)
+                        .map(|(name, (_, value))| (name, value))
+                        .collect::<Vec<_>>();
+                    if !names.is_empty() {
+                        names.sort_by_key(|(name, _)| *name);
+                        writeln!(self.output, "scope {depth}:").expect("Failed to write output");
+                        for (name, value) in names {
+                            writeln!(self.output, "  {name} = {value}")
+                                .expect("Failed to write output");
+                        }
+                        depth += 1;
+                    }
+                    scope = environment.parent.clone();
+                    visible = environment.parent_len;
+                }
+                Value::Unit
+            }
+            ("contains", [Value::List(elements), value]) => Value::Bool(elements.contains(value)),
+            ("contains", [Value::Set(elements), value]) => Value::Bool(elements.contains(value)),
+            ("contains", [Value::Dict(entries), key]) => {
+                Value::Bool(entries.iter().any(|(entry_key, _)| entry_key == key))
+            }
+            ("union", [Value::Set(lhs), Value::Set(rhs)]) => {
+                let mut elements = lhs.to_vec();
+                for value in rhs.iter() {
+                    if !elements.contains(value) {
+                        elements.push(value.clone());
+                    }
+                }
+                Value::Set(elements.into())
+            }
+            ("intersect", [Value::Set(lhs), Value::Set(rhs)]) => Value::Set(
+                lhs.iter()
+                    .filter(|value| rhs.contains(value))
+                    .cloned()
+                    .collect(),
+            ),
+            ("assert", [Value::Bool(true)] | [Value::Bool(true), Value::String(_)]) => {
+                Value::Bool(true)
+            }
+            ("assert", [Value::Bool(false)]) => {
+                return Err(RuntimeError::AssertionFailed(String::from("assertion failed")).into())
+            }
+            ("assert", [Value::Bool(false), Value::String(message)]) => {
+                return Err(RuntimeError::AssertionFailed(message.to_string()).into())
+            }
+            ("assert_eq", [lhs, rhs]) if lhs == rhs => Value::Bool(true),
+            ("assert_eq", [lhs, rhs, Value::String(_)]) if lhs == rhs => Value::Bool(true),
+            ("assert_eq", [lhs, rhs]) => {
+                return Err(RuntimeError::AssertionFailed(format!("{lhs} != {rhs}")).into())
+            }
+            ("assert_eq", [lhs, rhs, Value::String(message)]) => {
+                return Err(
+                    RuntimeError::AssertionFailed(format!("{message}: {lhs} != {rhs}")).into(),
+                )
+            }
+            ("range", [Value::Int(start), Value::Int(end)]) => {
+                Value::List(range(*start, *end, if start <= end { 1 } else { -1 }).into())
+            }
+            // The step must head towards `end`, or the range would never reach it.
+            ("range", [Value::Int(start), Value::Int(end), Value::Int(step)])
+                if *step == 0 || (start != end && (*step > 0) != (start < end)) =>
+            {
+                return Err(RuntimeError::TypeError(format!(
+                    "Cannot step from {start} to {end} by {step}"
+                ))
+                .into())
+            }
+            ("range", [Value::Int(start), Value::Int(end), Value::Int(step)]) => {
+                Value::List(range(*start, *end, *step).into())
+            }
+            ("first" | "head" | "last" | "rest" | "tail", [Value::List(elements)])
+                if elements.is_empty() =>
+            {
+                return Err(RuntimeError::EmptyList(name.to_string()).into())
+            }
+            ("first" | "head", [Value::List(elements)]) => elements[0].clone(),
+            ("last", [Value::List(elements)]) => elements[elements.len() - 1].clone(),
+            ("rest" | "tail", [Value::List(elements)]) => {
+                Value::List(elements.slice(1..elements.len()))
+            }
+            ("push", [Value::List(elements), value]) => Value::List(elements.push(value.clone())),
+            ("slice", [Value::List(elements), Value::Int(start), Value::Int(end)]) => {
+                Value::List(elements.slice(slice_range(elements.len(), *start, *end)))
+            }
+            ("slice", [Value::String(string), Value::Int(start), Value::Int(end)]) => {
+                let chars: Vec<char> = string.chars().collect();
+                Value::String(
+                    chars[slice_range(chars.len(), *start, *end)]
+                        .iter()
+                        .collect::<String>()
+                        .into(),
+                )
+            }
+            // Counts past the end of the list take or drop all of it.
+            ("take" | "drop", [Value::Int(count), Value::List(elements)]) => {
+                let count = (*count).clamp(0, elements.len() as i32) as usize;
+                Value::List(if name == "take" {
+                    elements.slice(0..count)
+                } else {
+                    elements.slice(count..elements.len())
+                })
+            }
+            ("flatten", [Value::List(elements)]) => {
+                let mut flattened = Vec::default();
+                for element in elements {
+                    flattened.extend(flatten_element(name, element.clone())?);
+                }
+                Value::List(flattened.into())
+            }
+            // Elements are compared structurally, so unlike a set they needn't
+            // be hashable.
+            ("unique", [Value::List(elements)]) => {
+                let mut unique: Vec<Value> = Vec::default();
+                for element in elements {
+                    if !unique.contains(element) {
+                        unique.push(element.clone());
+                    }
+                }
+                Value::List(unique.into())
+            }
+            ("is_empty", [Value::String(string)]) => Value::Bool(string.is_empty()),
+            ("is_empty", [Value::List(elements)]) => Value::Bool(elements.is_empty()),
+            ("is_empty", [Value::Set(elements) | Value::Tuple(elements)]) => {
+                Value::Bool(elements.is_empty())
+            }
+            ("is_empty", [Value::Dict(entries)]) => Value::Bool(entries.is_empty()),
+            // Positions are wrapped in an optional, `none` when not found.
+            ("index_of", [Value::List(elements), value]) => Value::Optional(
+                elements
+                    .iter()
+                    .position(|element| element == value)
+                    .map(|index| Box::new(Value::Int(index as i32))),
+            ),
+            ("index_of", [Value::String(string), Value::String(pattern)]) => Value::Optional(
+                string
+                    .find(&**pattern)
+                    .map(|byte| Box::new(Value::Int(string[..byte].chars().count() as i32))),
+            ),
+            ("trim", [Value::String(string)]) => Value::String(string.trim().into()),
+            ("starts_with", [Value::String(string), Value::String(prefix)]) => {
+                Value::Bool(string.starts_with(&**prefix))
+            }
+            ("ends_with", [Value::String(string), Value::String(suffix)]) => {
+                Value::Bool(string.ends_with(&**suffix))
+            }
+            // An empty search string matches nowhere rather than between
+            // every character.
+            ("replace", [Value::String(string), Value::String(from), Value::String(_)])
+                if from.is_empty() =>
+            {
+                Value::String(string.clone())
+            }
+            ("replace", [Value::String(string), Value::String(from), Value::String(to)]) => {
+                Value::String(string.replace(&**from, to).into())
+            }
+            // The width is a minimum, so longer strings are left as they are.
+            (
+                "pad_left" | "pad_right",
+                [Value::String(string), Value::Int(width), Value::String(pad)],
+            ) => {
+                let mut pad_chars = pad.chars();
+                let (Some(_), None) = (pad_chars.next(), pad_chars.next()) else {
+                    return Err(RuntimeError::TypeError(format!(
+                        "Padding of {name} must be a single character, got {pad:?}"
+                    ))
+                    .into());
+                };
+                let count = (*width).max(0) as usize;
+                let padding = pad.repeat(count.saturating_sub(string.chars().count()));
+                Value::String(if name == "pad_left" {
+                    (padding + string).into()
+                } else {
+                    (string.to_string() + &padding).into()
+                })
+            }
+            ("chars" | "to_list", [Value::String(string)]) => Value::List(
+                string
+                    .chars()
+                    .map(|c| Value::String(c.to_string().into()))
+                    .collect(),
+            ),
+            ("to_int", [Value::Char(c)]) => Value::Int(*c as i32),
+            ("to_int", [Value::Bool(value)]) => Value::Int(*value as i32),
+            ("to_bool", [Value::Int(value)]) => Value::Bool(*value != 0),
+            ("from_int", [Value::Int(code)]) => u32::try_from(*code)
+                .ok()
+                .and_then(char::from_u32)
+                .map(Value::Char)
+                .ok_or_else(|| RuntimeError::TypeError(format!("Invalid code point {code}")))?,
+            ("hex" | "bin", [Value::Int(value)]) => Value::String(to_radix(name, *value, 0).into()),
+            ("hex" | "bin", [Value::Int(value), Value::Int(width)]) => {
+                Value::String(to_radix(name, *value, *width).into())
+            }
+            ("bytes", [Value::String(string)]) => Value::Bytes(string.as_bytes().into()),
+            ("bytes", [Value::List(elements)]) => Value::Bytes(
+                elements
+                    .iter()
+                    .map(|element| match element {
+                        Value::Int(byte) => u8::try_from(*byte).map_err(|_| {
+                            RuntimeError::TypeError(format!("Byte {byte} is out of range"))
+                        }),
+                        element => Err(RuntimeError::TypeError(format!(
+                            "Cannot make bytes from a list containing {}",
+                            element.type_name()
+                        ))),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            ("byte_at", [Value::Bytes(bytes), Value::Int(index)]) => usize::try_from(*index)
+                .ok()
+                .and_then(|index| bytes.get(index))
+                .map(|byte| Value::Int(*byte as i32))
+                .ok_or_else(|| {
+                    RuntimeError::TypeError(format!(
+                        "Index {index} is out of range for {} bytes",
+                        bytes.len()
+                    ))
+                })?,
+            ("len", [Value::Bytes(bytes)]) => Value::Int(bytes.len() as i32),
+            ("len", [Value::String(string)]) => Value::Int(string.chars().count() as i32),
+            ("len", [Value::List(elements)]) => Value::Int(elements.len() as i32),
+            ("len", [Value::Set(elements) | Value::Tuple(elements)]) => {
+                Value::Int(elements.len() as i32)
+            }
+            ("len", [Value::Dict(entries)]) => Value::Int(entries.len() as i32),
+            // Invalid UTF-8 is an error rather than being replaced, so the
+            // string always holds exactly the bytes.
+            ("to_string", [Value::Bytes(bytes)]) => Value::String(
+                String::from_utf8(bytes.to_vec())
+                    .map_err(|err| {
+                        RuntimeError::TypeError(format!(
+                            "Bytes are not valid UTF-8 from index {}",
+                            err.utf8_error().valid_up_to()
+                        ))
+                    })?
+                    .into(),
+            ),
+            ("to_list", [Value::Bytes(bytes)]) => {
+                Value::List(bytes.iter().map(|byte| Value::Int(*byte as i32)).collect())
+            }
+            // `()` is the empty tuple, as there's no other way to write one.
+            ("to_list", [Value::Tuple(elements)]) => Value::List(elements.to_vec().into()),
+            ("to_list", [Value::Unit]) => Value::List(Vec::new().into()),
+            ("to_list", [list @ Value::List(_)]) => list.clone(),
+            ("to_tuple", [Value::List(elements)]) if elements.is_empty() => Value::Unit,
+            ("to_tuple", [Value::List(elements)]) => Value::Tuple(elements.to_vec().into()),
+            ("to_tuple", [tuple @ (Value::Tuple(_) | Value::Unit)]) => tuple.clone(),
+            ("read_file" | "write_file", _) if !self.allow_fs => {
+                return Err(RuntimeError::Forbidden(name.to_string()).into())
+            }
+            // Failures are thrown, so a script can catch a missing file.
+            ("read_file", [Value::String(path)]) => Value::String(
+                fs::read_to_string(&**path)
+                    .map_err(|err| io_error(path, err))?
+                    .into(),
+            ),
+            ("write_file", [Value::String(path), Value::String(contents)]) => {
+                fs::write(&**path, contents.as_bytes()).map_err(|err| io_error(path, err))?;
+                Value::Unit
+            }
+            ("write_file", [Value::String(path), Value::Bytes(contents)]) => {
+                fs::write(&**path, contents).map_err(|err| io_error(path, err))?;
+                Value::Unit
+            }
+            ("env", _) if !self.allow_env => {
+                return Err(RuntimeError::Forbidden(name.to_string()).into())
+            }
+            // A variable that isn't set, or isn't unicode, is `none`.
+            ("env", [Value::String(variable)]) => Value::Optional(
+                std::env::var(&**variable)
+                    .ok()
+                    .map(|value| Box::new(Value::String(value.into()))),
+            ),
+            ("sleep", [Value::Int(millis)]) => {
+                let millis = u64::try_from(*millis).map_err(|_| {
+                    RuntimeError::TypeError(format!("Cannot sleep for {millis} milliseconds"))
+                })?;
+                (self.sleeper)(Duration::from_millis(millis));
+                Value::Unit
+            }
+            // Invalid JSON is thrown, like a failed file read, so a script
+            // can catch it.
+            ("parse_json", [Value::String(text)]) => json::parse(text)
+                .map_err(|err| ControlFlow::Throw(Value::String(err.to_string().into())))?,
+            ("to_json", [value]) => Value::String(json::stringify(value)?.into()),
+            // The next line of input without its line ending, or `none` once
+            // the input runs out.
+            ("input", []) => {
+                let mut line = String::new();
+                let read = self.read_line(&mut line).map_err(|err| {
+                    ControlFlow::Throw(Value::String(format!("Failed to read input: {err}").into()))
+                })?;
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+                Value::Optional((read > 0).then(|| Box::new(Value::String(line.into()))))
+            }
+            ("sum" | "product", [Value::List(elements)]) => {
+                let identity = Value::Int(if name == "sum" { 0 } else { 1 });
+                elements
+                    .iter()
+                    .try_fold(identity, |total, element| accumulate(name, total, element))?
+            }
+            ("arity", [Value::Lambda(params, ..)]) => Value::Int(params.len() as i32),
+            ("delay", [func @ (Value::Lambda(..) | Value::Builtin(_) | Value::Native(_))]) => {
+                Value::Thunk(Box::new(func.clone()), Rc::default())
+            }
+            // Collections are already copied when assigned, but modules are
+            // shared, so this copies any it reaches.
+            ("clone", [value]) => deep_copy(value, &mut HashMap::new()),
+            ("flip", [func]) => Value::Native(Rc::new(Native::Flip(func.clone()))),
+            ("memoize", [func]) => {
+                Value::Native(Rc::new(Native::Memoize(func.clone(), RefCell::default())))
+            }
+            ("some", [value]) => Value::Optional(Some(Box::new(value.clone()))),
+            ("throw", [value]) => return Err(ControlFlow::Throw(value.clone())),
+            _ => {
+                return Err(RuntimeError::TypeError(format!(
+                    "invalid arguments to {name}: {args:?}"
+                ))
+                .into())
+            }
         };
-        Value::Bool(false)
+        Ok(value)
     }
 
-    fn interpret_if(
+    fn call_native<'e>(
         &mut self,
-        cond: &Expr,
-        true_branch: &Expr,
-        false_branch: &Option<Box<Expr>>,
-    ) -> Value {
-        let cond = self.interpret_expr(cond);
+        native: &Rc<Native>,
+        mut args: Vec<Value>,
+        stack: &mut Stack<'e>,
+    ) -> Result<Step<'e>, ControlFlow> {
+        match native.as_ref() {
+            Native::Flip(func) => {
+                if args.len() >= 2 {
+                    args.swap(0, 1);
+                }
+                Ok(Step::Call(func.clone(), args, None))
+            }
+            // The arguments are cache keys, so must be hashable
+            Native::Memoize(func, cache) => {
+                let key = args
+                    .iter()
+                    .cloned()
+                    .map(Key::new)
+                    .collect::<Result<Vec<_>, _>>()?;
+                if let Some(value) = cache.borrow().get(&key) {
+                    return Ok(Step::Done(Ok(value.clone())));
+                }
+                stack.push(Continuation::Memoize(native.clone(), key));
+                Ok(Step::Call(func.clone(), args, None))
+            }
+        }
+    }
 
-        if let Value::Bool(true) = cond {
-            self.interpret_expr(true_branch)
-        } else {
-            self.interpret_expr(
-                false_branch
-                    .as_ref()
-                    .expect("TODO: if expression must have else"),
-            )
+    /// Starts evaluating the file at `path` in a fresh global scope, for an
+    /// `import` if `import` and otherwise for its module. Relative paths are
+    /// resolved against the importing file, or the working directory outside
+    /// of any file. Each file is evaluated once, and importing it again uses
+    /// the same scope.
+    fn interpret_module<'e>(
+        &mut self,
+        path: &str,
+        import: bool,
+        stack: &mut Stack<'e>,
+    ) -> Result<Step<'e>, ControlFlow> {
+        let path = match self.imports.last().and_then(|file| file.parent()) {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        };
+        let path = path
+            .canonicalize()
+            .map_err(|err| RuntimeError::ImportFailed(format!("{}: {err}", path.display())))?;
+        if let Some(module) = self.modules.get(&path) {
+            return Ok(Step::Done(Ok(self.use_module(module.clone(), import))));
+        }
+        if self.imports.contains(&path) {
+            return Err(RuntimeError::CyclicImport(path.display().to_string()).into());
+        }
+
+        let source = fs::read_to_string(&path)
+            .map_err(|err| RuntimeError::ImportFailed(format!("{}: {err}", path.display())))?;
+        let tokens = Scanner::default()
+            .try_scan(source)
+            .map_err(|err| RuntimeError::ImportFailed(format!("{}: {err}", path.display())))?;
+        let exprs: Rc<[Expr]> = Parser::default().parse(tokens).into();
+
+        self.imports.push(path.clone());
+        stack.push(Continuation::Module {
+            path,
+            scope: self.replace_environment(Environment::default()),
+            coverage: self.coverage.take(),
+            import,
+        });
+        Ok(self.interpret_sequence(stack.bodies.keep(&exprs), false, stack))
+    }
+
+    /// Finishes evaluating the file at `path`, going back to `scope`.
+    fn leave_module(
+        &mut self,
+        path: PathBuf,
+        scope: Rc<RefCell<Environment>>,
+        coverage: Option<BTreeMap<usize, bool>>,
+        import: bool,
+        res: Result<Value, ControlFlow>,
+    ) -> Result<Value, ControlFlow> {
+        let module = mem::replace(&mut self.environment, scope);
+        self.coverage = coverage;
+        self.imports.pop();
+        match res {
+            Ok(_) => {
+                self.modules.insert(path, module.clone());
+                Ok(self.use_module(module, import))
+            }
+            // Only exceptions may escape a module; anything else stops here.
+            Err(flow @ ControlFlow::Throw(_)) => Err(flow),
+            Err(flow) => Err(RuntimeError::from(flow).into()),
+        }
+    }
+
+    /// Copies the bindings of `module` into the global scope for an `import`,
+    /// and otherwise gives the module as a value.
+    fn use_module(&mut self, module: Rc<RefCell<Environment>>, import: bool) -> Value {
+        if !import {
+            return Value::Module(module);
+        }
+
+        let mut global = self.environment.clone();
+        loop {
+            let parent = global.borrow().parent.clone();
+            match parent {
+                Some(parent) => global = parent,
+                None => break,
+            }
+        }
+        for (name, (_, value)) in &module.borrow().values {
+            global.borrow_mut().define(name, value.clone());
+        }
+        Value::Unit
+    }
+
+    /// Runs the first of `arms` whose pattern matches `value` and whose guard
+    /// holds, each in its own scope.
+    fn interpret_arms<'e>(
+        &mut self,
+        value: Value,
+        arms: &'e [(Expr, Option<Expr>, Expr)],
+        stack: &mut Stack<'e>,
+    ) -> Result<Step<'e>, ControlFlow> {
+        for (index, (pattern, guard, body)) in arms.iter().enumerate() {
+            let mut arm_env = self.child_environment();
+            match pattern {
+                Expr::Identifier(name, _) if name == "_" => {}
+                Expr::Identifier(name, _) => arm_env.define(name, value.clone()),
+                Expr::Literal(literal) if *literal == value => {}
+                Expr::Literal(_) => continue,
+                _ => panic!("Invalid pattern {pattern:?}"),
+            }
+
+            let scope = self.replace_environment(arm_env);
+            return Ok(match guard {
+                Some(guard) => stack.then(
+                    Continuation::MatchGuard(value, &arms[index..], scope),
+                    guard,
+                ),
+                None => stack.then(Continuation::Scope(scope), body),
+            });
         }
+
+        Err(RuntimeError::NoMatch(value).into())
     }
 
-    fn interpret_while(&mut self, cond: &Expr, body: &Expr) -> Value {
-        let mut val = self.interpret_expr(cond);
-        while let Value::Bool(true) = val {
-            self.interpret_expr(body);
+    /// Compares `value` with the first of `cases`, or runs the default once
+    /// there are no cases left.
+    fn interpret_cases<'e>(
+        &mut self,
+        value: Value,
+        cases: &'e [(Expr, Expr)],
+        default: Option<&'e Expr>,
+        stack: &mut Stack<'e>,
+    ) -> Step<'e> {
+        match (cases.first(), default) {
+            (Some((case, _)), _) => {
+                stack.then(Continuation::SwitchCase(value, cases, default), case)
+            }
+            (None, Some(default)) => Step::Eval(default),
+            (None, None) => Step::Done(Ok(Value::Unit)),
+        }
+    }
 
-            val = self.interpret_expr(cond);
+    /// Starts a `while`, `repeat` or `loop`. Each absorbs any `break` or
+    /// `continue` aimed at it: unlabeled ones, or those naming its `label`.
+    fn interpret_loop<'e>(
+        &mut self,
+        expr: &'e Expr,
+        label: Option<&'e str>,
+        stack: &mut Stack<'e>,
+    ) -> Step<'e> {
+        match expr {
+            Expr::While(cond, _, _) => stack.then(Continuation::LoopCondition(expr, label), cond),
+            Expr::Repeat(body, _) | Expr::Loop(body) => {
+                stack.then(Continuation::LoopBody(expr, label), body)
+            }
+            expr => panic!("Invalid labeled expression {expr:?}"),
         }
-        Value::Bool(false)
     }
 
-    fn interpret_decl(&mut self, lhs: &Expr, init: &Option<Box<Expr>>) -> Value {
-        let init = self.interpret_expr(
-            init.as_ref()
-                .expect("TODO: declarations must have initial value"),
-        );
+    /// Goes on with a loop after a pass through its body.
+    fn continue_loop<'e>(
+        &mut self,
+        expr: &'e Expr,
+        label: Option<&'e str>,
+        stack: &mut Stack<'e>,
+    ) -> Step<'e> {
+        match expr {
+            Expr::While(cond, _, _) | Expr::Repeat(_, cond) => {
+                stack.then(Continuation::LoopCondition(expr, label), cond)
+            }
+            Expr::Loop(body) => stack.then(Continuation::LoopBody(expr, label), body),
+            expr => unreachable!("Not a loop: {expr:?}"),
+        }
+    }
 
-        let Expr::Identifier(identifier) = lhs else {
-            panic!("Invalid LHS of declaration")
+    /// Evaluates the body of a comprehension for its next element that
+    /// passes the guard, with the element bound to the pattern.
+    fn interpret_comprehension<'e>(
+        &mut self,
+        mut comprehension: Box<Comprehension<'e>>,
+        stack: &mut Stack<'e>,
+    ) -> Result<Step<'e>, ControlFlow> {
+        let Some(element) = comprehension.elements.next() else {
+            return Ok(Step::Done(Ok(Value::List(comprehension.values.into()))));
         };
+        let mut env = self.child_environment();
+        env.define_pattern(comprehension.pattern, element)?;
+        self.environment = Rc::new(RefCell::new(env));
+        Ok(match comprehension.guard {
+            Some(guard) => stack.then(Continuation::Comprehension(comprehension, true), guard),
+            None => {
+                let body = comprehension.body;
+                stack.then(Continuation::Comprehension(comprehension, false), body)
+            }
+        })
+    }
+
+    fn interpret_decl(&mut self, lhs: &Expr, init: Value) -> Result<Value, ControlFlow> {
+        // `q, r := divmod! 17 5` destructures a tuple as parameters do.
         self.environment
             .borrow_mut()
-            .define(identifier, init.clone());
-        init
+            .define_pattern(lhs, init.clone())?;
+        Ok(init)
     }
+}
 
-    fn interpret_unary(&mut self, op: &Token, e: &Expr) -> Value {
-        let value = self.interpret_expr(e);
+/// Adds the lines in `expr` that coverage can see run to `coverage`, as not
+/// yet run. Patterns and the names declarations bind are never evaluated
+/// themselves, so only the expressions around them count.
+fn coverable_lines(expr: &Expr, coverage: &mut BTreeMap<usize, bool>) {
+    if let Some(location) = expr.location() {
+        coverage.entry(location.line).or_insert(false);
+    }
+    let children: Vec<&Expr> = match expr {
+        Expr::Lambda(_, body, _) => body.iter().collect(),
+        Expr::Declaration(_, _, init) => init.as_deref().into_iter().collect(),
+        Expr::Comprehension(body, _, iterable, guard) => [body.as_ref(), iterable.as_ref()]
+            .into_iter()
+            .chain(guard.as_deref())
+            .collect(),
+        Expr::Match(scrutinee, arms) => std::iter::once(scrutinee.as_ref())
+            .chain(
+                arms.iter()
+                    .flat_map(|(_, guard, body)| guard.iter().chain([body])),
+            )
+            .collect(),
+        expr => expr.children(),
+    };
+    for child in children {
+        coverable_lines(child, coverage);
+    }
+}
 
-        match (&op.token_type, &value) {
-            (TokenType::Minus, Value::Int(x)) => Value::Int(-x),
-            (TokenType::Bang, Value::Bool(x)) => Value::Bool(!x),
-            _ => panic!("Type error: {op:?} {value:?}"),
+/// A short, source-like rendering of `expr` for traces. Bodies of blocks,
+/// loops and functions are left out, as their expressions are traced in turn.
+fn describe(expr: &Expr) -> String {
+    let list = |exprs: &[Expr]| exprs.iter().map(describe).collect::<Vec<_>>().join(", ");
+    match expr {
+        Expr::Identifier(name, _) => name.clone(),
+        Expr::Literal(value) => value.to_string(),
+        Expr::Binary(lhs, op, rhs) => {
+            format!("{} {} {}", describe(lhs), operator_name(op), describe(rhs))
+        }
+        Expr::Chain(first, comparisons) => comparisons
+            .iter()
+            .fold(describe(first), |chain, (op, operand)| {
+                format!("{chain} {} {}", operator_name(op), describe(operand))
+            }),
+        Expr::Unary(op, operand) => {
+            let op = if op.token_type == TokenType::Bang {
+                "!"
+            } else {
+                "-"
+            };
+            format!("{op}{}", describe(operand))
+        }
+        Expr::Group(expr) => format!("({})", describe(expr)),
+        Expr::Propagate(expr) => format!("{}?", describe(expr)),
+        Expr::Tuple(elements) => format!("({})", list(elements)),
+        Expr::List(elements) => format!("[{}]", list(elements)),
+        Expr::Set(elements) => format!("{{{}}}", list(elements)),
+        Expr::Dict(_) => String::from("{...}"),
+        Expr::FunctionCall(callee, args, _) if args.is_empty() => format!("{}!", describe(callee)),
+        Expr::FunctionCall(callee, args, _) => {
+            let args = args.iter().map(describe).collect::<Vec<_>>().join(" ");
+            format!("{}! {args}", describe(callee))
         }
+        Expr::Get(obj, name) => format!("{}.{name}", describe(obj)),
+        Expr::Declaration(lhs, _, _) => format!("{} := ...", describe(lhs)),
+        Expr::Assignment(lhs, ..) => format!("{} = ...", describe(lhs)),
+        Expr::Block(_) => String::from("block"),
+        Expr::While(cond, _, _) => format!("while {}", describe(cond)),
+        Expr::Collect(cond, _) => format!("collect while {}", describe(cond)),
+        Expr::Comprehension(..) => String::from("[... for ...]"),
+        Expr::Repeat(..) => String::from("repeat"),
+        Expr::Loop(_) => String::from("loop"),
+        Expr::Return(expr) => format!("return {}", describe(expr)),
+        Expr::Break(_) => String::from("break"),
+        Expr::Continue(_) => String::from("continue"),
+        Expr::Labeled(label, _) => format!("{label}:"),
+        Expr::Try(..) => String::from("try"),
+        Expr::Import(path) | Expr::Module(path) => format!("import {path:?}"),
+        Expr::If(cond, _, _) => format!("if {}", describe(cond)),
+        Expr::Guard(cond, _) => format!("guard {}", describe(cond)),
+        Expr::Match(scrutinee, _) => format!("match {}", describe(scrutinee)),
+        Expr::Switch(scrutinee, _, _) => format!("switch {}", describe(scrutinee)),
+        Expr::BuiltinFunction(token) => format!("{:?}", token.token_type).to_lowercase(),
+        Expr::Lambda(params, _, _) => {
+            let params = params.iter().map(describe).collect::<Vec<_>>().join(" ");
+            format!("fn {params} -> ...")
+        }
+        Expr::CaptureByValue(_, lambda) => describe(lambda),
     }
+}
 
-    fn interpret_binary(&mut self, lhs: &Expr, op: &Token, rhs: &Expr) -> Value {
-        let lhs = self.interpret_expr(lhs);
-        let rhs = self.interpret_expr(rhs);
+/// How the operator `op` is written.
+fn operator_name(op: &Token) -> String {
+    OPERATORS
+        .iter()
+        .find(|(_, token_type)| *token_type == op.token_type)
+        .map_or_else(
+            || format!("{:?}", op.token_type),
+            |(name, _)| name.to_string(),
+        )
+}
 
-        match (&op.token_type, lhs, rhs) {
-            (TokenType::Minus, Value::Int(lhs), Value::Int(rhs)) => Value::Int(lhs - rhs),
-            (TokenType::Plus, Value::Int(lhs), Value::Int(rhs)) => Value::Int(lhs + rhs),
-            (TokenType::Star, Value::Int(lhs), Value::Int(rhs)) => Value::Int(lhs * rhs),
-            (TokenType::Mod, Value::Int(lhs), Value::Int(rhs)) => Value::Int(lhs % rhs),
-            (TokenType::SlashSlash, Value::Int(lhs), Value::Int(rhs)) => Value::Int(lhs / rhs),
-            (TokenType::AndAnd, Value::Bool(lhs), Value::Bool(rhs)) => Value::Bool(lhs && rhs),
-            (TokenType::OrOr, Value::Bool(lhs), Value::Bool(rhs)) => Value::Bool(lhs || rhs),
-            (TokenType::Greater, Value::Int(lhs), Value::Int(rhs)) => Value::Bool(lhs > rhs),
-            (TokenType::GreaterEqual, Value::Int(lhs), Value::Int(rhs)) => Value::Bool(lhs >= rhs),
-            (TokenType::Less, Value::Int(lhs), Value::Int(rhs)) => Value::Bool(lhs < rhs),
-            (TokenType::LessEqual, Value::Int(lhs), Value::Int(rhs)) => Value::Bool(lhs <= rhs),
-            (TokenType::EqualEqual, Value::Bool(lhs), Value::Bool(rhs)) => Value::Bool(lhs == rhs),
-            (TokenType::EqualEqual, Value::Int(lhs), Value::Int(rhs)) => Value::Bool(lhs == rhs),
-            (TokenType::EqualEqual, Value::String(lhs), Value::String(rhs)) => {
-                Value::Bool(lhs == rhs)
-            }
-            (TokenType::BangEqual, Value::Bool(lhs), Value::Bool(rhs)) => Value::Bool(lhs != rhs),
-            (TokenType::BangEqual, Value::Int(lhs), Value::Int(rhs)) => Value::Bool(lhs != rhs),
-            (TokenType::BangEqual, Value::String(lhs), Value::String(rhs)) => {
-                Value::Bool(lhs != rhs)
-            }
-            _ => panic!("Type error"),
+/// The exception a failed file operation on `path` throws.
+fn io_error(path: &str, err: std::io::Error) -> ControlFlow {
+    ControlFlow::Throw(Value::String(format!("{path}: {err}").into()))
+}
+
+/// A copy of `value` sharing no modules with it, as `clone!` makes. `copies`
+/// maps each module copied so far to its copy, so a module reached twice is
+/// copied once, and lambdas made at the top of a copied module see the copy.
+fn deep_copy(
+    value: &Value,
+    copies: &mut HashMap<*const RefCell<Environment>, Rc<RefCell<Environment>>>,
+) -> Value {
+    match value {
+        Value::Module(module) => Value::Module(copy_module(module, copies)),
+        Value::Lambda(params, body, closure, visible) => Value::Lambda(
+            params.clone(),
+            body.clone(),
+            copies
+                .get(&Rc::as_ptr(closure))
+                .cloned()
+                .unwrap_or_else(|| closure.clone()),
+            *visible,
+        ),
+        Value::List(elements) => Value::List(
+            elements
+                .iter()
+                .map(|element| deep_copy(element, copies))
+                .collect::<Vec<_>>()
+                .into(),
+        ),
+        Value::Tuple(elements) => Value::Tuple(
+            elements
+                .iter()
+                .map(|element| deep_copy(element, copies))
+                .collect(),
+        ),
+        Value::Set(elements) => Value::Set(
+            elements
+                .iter()
+                .map(|element| deep_copy(element, copies))
+                .collect(),
+        ),
+        Value::Dict(entries) => Value::Dict(
+            entries
+                .iter()
+                .map(|(key, value)| (deep_copy(key, copies), deep_copy(value, copies)))
+                .collect(),
+        ),
+        Value::Optional(Some(value)) => Value::Optional(Some(Box::new(deep_copy(value, copies)))),
+        value => value.clone(),
+    }
+}
+
+/// Copies the bindings of `module` into a new one for `deep_copy`.
+fn copy_module(
+    module: &Rc<RefCell<Environment>>,
+    copies: &mut HashMap<*const RefCell<Environment>, Rc<RefCell<Environment>>>,
+) -> Rc<RefCell<Environment>> {
+    if let Some(copy) = copies.get(&Rc::as_ptr(module)) {
+        return copy.clone();
+    }
+
+    let copy = Rc::new(RefCell::new(Environment {
+        parent: module.borrow().parent.clone(),
+        parent_len: module.borrow().parent_len,
+        values: HashMap::new(),
+    }));
+    copies.insert(Rc::as_ptr(module), copy.clone());
+    let values = module.borrow().values.clone();
+    let values = values
+        .into_iter()
+        .map(|(name, (index, value))| (name, (index, deep_copy(&value, copies))))
+        .collect();
+    copy.borrow_mut().values = values;
+    copy
+}
+
+/// The elements of a list being flattened by the builtin `name`.
+fn flatten_element(name: &str, element: Value) -> Result<Vec<Value>, RuntimeError> {
+    match element {
+        Value::List(elements) => Ok(elements.to_vec()),
+        element => Err(RuntimeError::TypeError(format!(
+            "Cannot {name} a list containing {}",
+            element.type_name()
+        ))),
+    }
+}
+
+/// Whether the predicate passed to the builtin `name` held, which it must
+/// return as a bool.
+fn predicate(name: &str, value: Value) -> Result<bool, RuntimeError> {
+    match value {
+        Value::Bool(value) => Ok(value),
+        value => Err(RuntimeError::TypeError(format!(
+            "Predicate of {name} returned {}, expected Bool",
+            value.type_name()
+        ))),
+    }
+}
+
+/// Reads a field, which for a dict is the entry with that string key and for
+/// a module is a top-level binding.
+fn get_field(value: Value, name: &str) -> Result<Value, RuntimeError> {
+    match value {
+        Value::Dict(entries) => entries
+            .iter()
+            .find(|(key, _)| matches!(key, Value::String(key) if **key == *name))
+            .map(|(_, value)| value.clone())
+            .ok_or_else(|| RuntimeError::UndefinedField(name.to_string())),
+        Value::Module(module) => Environment::member(&module, name),
+        value => Err(RuntimeError::TypeError(format!(
+            "Cannot access field {name:?} of {}",
+            value.type_name()
+        ))),
+    }
+}
+
+/// Applies a unary operator to its evaluated operand.
+fn apply_unary(op: &Token, value: Value) -> Result<Value, RuntimeError> {
+    let value = match (&op.token_type, &value) {
+        (TokenType::Minus, Value::Int(x)) => match x.checked_neg() {
+            Some(x) => Value::Int(x),
+            None => return Err(RuntimeError::IntegerOverflow.at(op.location)),
+        },
+        (TokenType::Minus, Value::Float(x)) => Value::Float(-x),
+        (TokenType::Bang, Value::Bool(x)) => Value::Bool(!x),
+        (token_type, value) => {
+            return Err(RuntimeError::TypeError(format!(
+                "Cannot apply {token_type:?} to {}",
+                value.type_name()
+            ))
+            .at(op.location))
         }
+    };
+    Ok(value)
+}
+
+/// The result of checked int arithmetic, or an overflow error.
+fn checked(value: Option<i32>) -> Result<Value, RuntimeError> {
+    value.map(Value::Int).ok_or(RuntimeError::IntegerOverflow)
+}
+
+/// Applies a binary operator to two evaluated operands.
+///
+/// | Operator          | Meaning                                      |
+/// |-------------------|----------------------------------------------|
+/// | `+` `-` `*`       | Arithmetic; `string * n` repeats the string  |
+/// | `/`               | Division, always giving a float              |
+/// | `//`              | Division rounded down                        |
+/// | `%`               | Remainder, with the sign of the left operand |
+/// | `%%`              | Euclidean remainder, never negative          |
+/// | `==` `!=`         | Structural equality                          |
+/// | `<` `<=` `>` `>=` | Ordering                                     |
+/// | `&&` `\|\|`       | Logical and and or, short-circuiting         |
+///
+/// Mixing ints and floats gives a float, and int results that don't fit in an
+/// int are an overflow error. Comments start with `#`, so `//` is only ever
+/// division.
+fn apply_operator(op: &TokenType, lhs: Value, rhs: Value) -> Result<Value, RuntimeError> {
+    // Mixed arithmetic promotes the integer operand to a float
+    let (lhs, rhs) = match (lhs, rhs) {
+        (Value::Int(lhs), Value::Float(rhs)) => (Value::Float(lhs as f64), Value::Float(rhs)),
+        (Value::Float(lhs), Value::Int(rhs)) => (Value::Float(lhs), Value::Float(rhs as f64)),
+        operands => operands,
+    };
+
+    if let TokenType::EqualEqual | TokenType::BangEqual = op {
+        let equal = lhs.equals(&rhs)?;
+        return Ok(Value::Bool(equal == (*op == TokenType::EqualEqual)));
+    }
+
+    if let TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual =
+        op
+    {
+        let ordering = lhs.compare(&rhs)?;
+        return Ok(Value::Bool(match op {
+            TokenType::Greater => ordering.is_gt(),
+            TokenType::GreaterEqual => ordering.is_ge(),
+            TokenType::Less => ordering.is_lt(),
+            _ => ordering.is_le(),
+        }));
     }
+
+    let value = match (op, lhs, rhs) {
+        (TokenType::Minus, Value::Int(lhs), Value::Int(rhs)) => checked(lhs.checked_sub(rhs))?,
+        (TokenType::Plus, Value::Int(lhs), Value::Int(rhs)) => checked(lhs.checked_add(rhs))?,
+        (TokenType::Star, Value::Int(lhs), Value::Int(rhs)) => checked(lhs.checked_mul(rhs))?,
+        (TokenType::Star, Value::String(_), Value::Int(count)) if count < 0 => {
+            return Err(RuntimeError::TypeError(format!(
+                "Cannot repeat a string {count} times"
+            )))
+        }
+        (TokenType::Star, Value::String(lhs), Value::Int(rhs)) => {
+            Value::String(lhs.repeat(rhs as usize).into())
+        }
+        (
+            TokenType::Mod | TokenType::ModMod | TokenType::SlashSlash,
+            Value::Int(_),
+            Value::Int(0),
+        ) => return Err(RuntimeError::DivisionByZero),
+        (TokenType::Mod, Value::Int(lhs), Value::Int(rhs)) => checked(lhs.checked_rem(rhs))?,
+        (TokenType::ModMod, Value::Int(lhs), Value::Int(rhs)) => {
+            checked(lhs.checked_rem_euclid(rhs))?
+        }
+        (TokenType::Slash, Value::Int(lhs), Value::Int(rhs)) => {
+            Value::Float(lhs as f64 / rhs as f64)
+        }
+        (TokenType::SlashSlash, Value::Int(lhs), Value::Int(rhs)) => {
+            let quotient = lhs.checked_div(rhs).ok_or(RuntimeError::IntegerOverflow)?;
+            if lhs % rhs != 0 && (lhs < 0) != (rhs < 0) {
+                Value::Int(quotient - 1)
+            } else {
+                Value::Int(quotient)
+            }
+        }
+        (TokenType::Minus, Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs - rhs),
+        (TokenType::Plus, Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs + rhs),
+        (TokenType::Star, Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs * rhs),
+        (TokenType::Slash, Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs / rhs),
+        (TokenType::SlashSlash, Value::Float(lhs), Value::Float(rhs)) => {
+            Value::Float((lhs / rhs).floor())
+        }
+        (TokenType::Mod, Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs % rhs),
+        (TokenType::ModMod, Value::Float(lhs), Value::Float(rhs)) => {
+            Value::Float(lhs.rem_euclid(rhs))
+        }
+        (TokenType::AndAnd, Value::Bool(lhs), Value::Bool(rhs)) => Value::Bool(lhs && rhs),
+        (TokenType::OrOr, Value::Bool(lhs), Value::Bool(rhs)) => Value::Bool(lhs || rhs),
+        (op, lhs, rhs) => {
+            return Err(RuntimeError::TypeError(format!(
+                "Cannot apply {op:?} to {} and {}",
+                lhs.type_name(),
+                rhs.type_name()
+            )))
+        }
+    };
+    Ok(value)
 }
@@ -0,0 +1,377 @@
+use std::fmt;
+
+use crate::{interpreter::RuntimeError, parser::Value};
+
+/// Why some text isn't valid JSON, and the character where reading it failed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonError {
+    pub index: usize,
+    pub message: String,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid JSON at character {}: {}",
+            self.index, self.message
+        )
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+/// How deeply arrays and objects may nest in a document being read. Reading
+/// recurses per level, so this keeps a hostile document from overflowing the
+/// stack.
+const MAX_NESTING: usize = 512;
+
+/// Reads a JSON document. Objects become dicts with string keys, arrays
+/// become lists and `null` becomes `()`. A number without a fraction or
+/// exponent that fits an int becomes one, and any other number a float.
+pub fn parse(text: &str) -> Result<Value, JsonError> {
+    let mut reader = Reader {
+        chars: text.chars().collect(),
+        index: 0,
+        depth: 0,
+    };
+    let value = reader.value()?;
+    reader.skip_whitespace();
+    if reader.peek().is_some() {
+        return Err(reader.error("Unexpected text after the value"));
+    }
+    Ok(value)
+}
+
+/// Writes `value` as JSON. Tuples and sets are written as arrays, `()` and
+/// `none` as `null`, and `some(x)` as `x`. Functions, modules, bytes, dicts
+/// with keys that aren't strings and non-finite floats have no JSON form and
+/// are type errors.
+pub fn stringify(value: &Value) -> Result<String, RuntimeError> {
+    let mut json = String::new();
+    write_value(&mut json, value)?;
+    Ok(json)
+}
+
+fn write_value(json: &mut String, value: &Value) -> Result<(), RuntimeError> {
+    match value {
+        Value::Unit | Value::Optional(None) => json.push_str("null"),
+        Value::Optional(Some(value)) => write_value(json, value)?,
+        Value::Bool(value) => json.push_str(&value.to_string()),
+        Value::Int(value) => json.push_str(&value.to_string()),
+        Value::Float(value) if value.is_finite() => json.push_str(&format!("{value:?}")),
+        Value::String(value) => write_string(json, value),
+        Value::Char(value) => write_string(json, &value.to_string()),
+        Value::List(elements) => write_array(json, elements)?,
+        Value::Tuple(elements) | Value::Set(elements) => write_array(json, elements.iter())?,
+        Value::Dict(entries) => {
+            json.push('{');
+            for (i, (key, value)) in entries.iter().enumerate() {
+                let Value::String(key) = key else {
+                    return Err(RuntimeError::TypeError(format!(
+                        "JSON object keys must be strings, not {}",
+                        key.type_name()
+                    )));
+                };
+                if i > 0 {
+                    json.push(',');
+                }
+                write_string(json, key);
+                json.push(':');
+                write_value(json, value)?;
+            }
+            json.push('}');
+        }
+        value => {
+            return Err(RuntimeError::TypeError(format!(
+                "Cannot convert {} to JSON",
+                match value {
+                    Value::Float(value) => value.to_string(),
+                    value => value.type_name().to_string(),
+                }
+            )))
+        }
+    }
+    Ok(())
+}
+
+fn write_array<'a>(
+    json: &mut String,
+    elements: impl IntoIterator<Item = &'a Value>,
+) -> Result<(), RuntimeError> {
+    json.push('[');
+    for (i, element) in elements.into_iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        write_value(json, element)?;
+    }
+    json.push(']');
+    Ok(())
+}
+
+fn write_string(json: &mut String, string: &str) {
+    json.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+struct Reader {
+    chars: Vec<char>,
+    index: usize,
+    /// How many arrays and objects enclose the current position.
+    depth: usize,
+}
+
+impl Reader {
+    fn error(&self, message: &str) -> JsonError {
+        JsonError {
+            index: self.index,
+            message: message.to_string(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    fn matches(&mut self, c: char) -> bool {
+        let matched = self.peek() == Some(c);
+        if matched {
+            self.index += 1;
+        }
+        matched
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), JsonError> {
+        if self.matches(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected {c:?}")))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.index += 1;
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, JsonError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.nested(Self::object),
+            Some('[') => self.nested(Self::array),
+            Some('"') => self.string().map(|string| Value::String(string.into())),
+            Some('-' | '0'..='9') => self.number(),
+            Some('a'..='z') => self.keyword(),
+            _ => Err(self.error("Expected a value")),
+        }
+    }
+
+    /// Reads an array or object with `read`, one level deeper.
+    fn nested(
+        &mut self,
+        read: fn(&mut Self) -> Result<Value, JsonError>,
+    ) -> Result<Value, JsonError> {
+        if self.depth == MAX_NESTING {
+            return Err(self.error("Too deeply nested"));
+        }
+        self.depth += 1;
+        let value = read(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Result<Value, JsonError> {
+        self.expect('{')?;
+        let mut entries: Vec<(Value, Value)> = Vec::default();
+        self.skip_whitespace();
+        if self.matches('}') {
+            return Ok(Value::Dict(entries.into()));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("Expected a string key"));
+            }
+            let key = Value::String(self.string()?.into());
+            self.skip_whitespace();
+            self.expect(':')?;
+            let value = self.value()?;
+            // A repeated key keeps its last value, as in a dict literal.
+            match entries.iter_mut().find(|(entry_key, _)| *entry_key == key) {
+                Some(entry) => entry.1 = value,
+                None => entries.push((key, value)),
+            }
+            self.skip_whitespace();
+            if self.matches('}') {
+                return Ok(Value::Dict(entries.into()));
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, JsonError> {
+        self.expect('[')?;
+        let mut elements = Vec::default();
+        self.skip_whitespace();
+        if self.matches(']') {
+            return Ok(Value::List(elements.into()));
+        }
+        loop {
+            elements.push(self.value()?);
+            self.skip_whitespace();
+            if self.matches(']') {
+                return Ok(Value::List(elements.into()));
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(self.error("Unterminated string"));
+            };
+            match c {
+                '"' => {
+                    self.index += 1;
+                    return Ok(string);
+                }
+                '\\' => {
+                    self.index += 1;
+                    string.push(self.escape()?);
+                }
+                c if c < ' ' => return Err(self.error("Control character in string")),
+                c => {
+                    self.index += 1;
+                    string.push(c);
+                }
+            }
+        }
+    }
+
+    /// Reads the rest of an escape sequence, after the backslash.
+    fn escape(&mut self) -> Result<char, JsonError> {
+        let c = match self.peek() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                self.index += 1;
+                let unit = self.code_unit()?;
+                // Characters outside the basic plane are written as a pair
+                // of surrogates.
+                let code = if (0xd800..0xdc00).contains(&unit) {
+                    if !(self.matches('\\') && self.matches('u')) {
+                        return Err(self.error("Expected a low surrogate"));
+                    }
+                    let low = self.code_unit()?;
+                    if !(0xdc00..0xe000).contains(&low) {
+                        return Err(self.error("Expected a low surrogate"));
+                    }
+                    0x10000 + ((unit - 0xd800) << 10) + (low - 0xdc00)
+                } else {
+                    unit
+                };
+                return char::from_u32(code).ok_or_else(|| self.error("Invalid code point"));
+            }
+            _ => return Err(self.error("Invalid escape")),
+        };
+        self.index += 1;
+        Ok(c)
+    }
+
+    /// Reads the four hex digits of a `\u` escape.
+    fn code_unit(&mut self) -> Result<u32, JsonError> {
+        let digits = self
+            .chars
+            .get(self.index..self.index + 4)
+            .map(|digits| digits.iter().collect::<String>())
+            .ok_or_else(|| self.error("Expected four hex digits"))?;
+        let unit =
+            u32::from_str_radix(&digits, 16).map_err(|_| self.error("Expected four hex digits"))?;
+        self.index += 4;
+        Ok(unit)
+    }
+
+    fn number(&mut self) -> Result<Value, JsonError> {
+        let start = self.index;
+        self.matches('-');
+        if !self.matches('0') {
+            self.digits()?;
+        }
+        let mut integer = true;
+        if self.matches('.') {
+            integer = false;
+            self.digits()?;
+        }
+        if self.matches('e') || self.matches('E') {
+            integer = false;
+            if !self.matches('+') {
+                self.matches('-');
+            }
+            self.digits()?;
+        }
+
+        let text = self.chars[start..self.index].iter().collect::<String>();
+        match text.parse::<i32>() {
+            Ok(value) if integer => Ok(Value::Int(value)),
+            _ => text
+                .parse::<f64>()
+                .map(Value::Float)
+                .map_err(|_| JsonError {
+                    index: start,
+                    message: String::from("Invalid number"),
+                }),
+        }
+    }
+
+    fn digits(&mut self) -> Result<(), JsonError> {
+        if !matches!(self.peek(), Some('0'..='9')) {
+            return Err(self.error("Expected a digit"));
+        }
+        while matches!(self.peek(), Some('0'..='9')) {
+            self.index += 1;
+        }
+        Ok(())
+    }
+
+    fn keyword(&mut self) -> Result<Value, JsonError> {
+        let start = self.index;
+        while matches!(self.peek(), Some('a'..='z')) {
+            self.index += 1;
+        }
+        match self.chars[start..self.index]
+            .iter()
+            .collect::<String>()
+            .as_str()
+        {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            "null" => Ok(Value::Unit),
+            _ => Err(JsonError {
+                index: start,
+                message: String::from("Expected a value"),
+            }),
+        }
+    }
+}
@@ -1,35 +1,90 @@
 use std::{
     fs::read_to_string,
-    io::{self, Write},
+    io::{self, BufRead, Write},
     path::Path,
+    time::Duration,
 };
 
+pub use checker::Diagnostic;
 use interpreter::Interpreter;
+pub use interpreter::{Frame, RuntimeError};
 use parser::{Expr, Parser, Value};
-use scanner::{Scanner, Token};
+pub use scanner::ScanError;
+use scanner::Scanner;
+pub use scanner::{Location, Token, TokenType, DEFAULT_MAX_LITERAL_LENGTH};
 
+mod checker;
 mod interpreter;
+mod json;
 pub mod parser;
 mod scanner;
 
-pub struct Compiler<'a, T: Write> {
+/// The outcome of running a zeal file's top-level assertions.
+#[derive(Debug, Default, PartialEq)]
+pub struct TestReport {
+    pub passed: usize,
+    pub failures: Vec<TestFailure>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TestFailure {
+    pub line: usize,
+    pub message: String,
+}
+
+/// The lines of a program that ran and didn't, in order. Only lines holding
+/// an expression coverage can see, such as a name, call or operator, count.
+#[derive(Debug, Default, PartialEq)]
+pub struct CoverageReport {
+    pub executed: Vec<usize>,
+    pub missed: Vec<usize>,
+}
+
+/// Runs programs that write to `T` and read their input from `I`. Without
+/// an input reader, `input!` finds no input.
+pub struct Compiler<'a, T: Write, I: BufRead = io::Empty> {
     scanner: Scanner,
     parser: Parser,
-    interpreter: Interpreter<'a, T>,
+    interpreter: Interpreter<'a, T, I>,
 }
 
 impl<'a, T: Write> Compiler<'a, T> {
     pub fn new(output: &'a mut T) -> Self {
+        Compiler::with_max_literal_length(output, DEFAULT_MAX_LITERAL_LENGTH)
+    }
+
+    /// A compiler whose scanner rejects string and number literals longer
+    /// than `max_literal_length` bytes with a [`ScanError`].
+    pub fn with_max_literal_length(output: &'a mut T, max_literal_length: usize) -> Self {
+        Compiler {
+            scanner: Scanner::with_max_literal_length(max_literal_length),
+            parser: Parser::default(),
+            interpreter: Interpreter::new(output, io::empty()),
+        }
+    }
+}
+
+impl<'a, T: Write, I: BufRead> Compiler<'a, T, I> {
+    /// A compiler whose programs read lines from `input` with `input!`.
+    pub fn with_input(output: &'a mut T, input: I) -> Self {
         Compiler {
             scanner: Scanner::default(),
             parser: Parser::default(),
-            interpreter: Interpreter::new(output),
+            interpreter: Interpreter::new(output, input),
         }
     }
 
+    /// Reads a line from the same input as `input!`, for a host that shares
+    /// it with the program, such as a REPL.
+    pub fn read_line(&mut self, line: &mut String) -> io::Result<usize> {
+        self.interpreter.read_line(line)
+    }
+
     pub fn run(&mut self, path: &Path) -> io::Result<()> {
         let contents = read_to_string(path)?;
-        let _token_stream = self.scanner.scan(contents);
+        let tokens = self.scanner.scan(contents);
+        let exprs = self.parser.parse(tokens);
+        self.interpreter.interpret_file(path, exprs);
         Ok(())
     }
 
@@ -41,11 +96,162 @@ impl<'a, T: Write> Compiler<'a, T> {
         self.scanner.scan(String::from(line))
     }
 
+    pub fn try_scan_line(&mut self, line: &str) -> Result<Vec<scanner::Token>, ScanError> {
+        self.scanner.try_scan(String::from(line))
+    }
+
+    /// Whether `source` is a whole statement, or needs more lines first. Input
+    /// is incomplete while a string or bracket is unclosed, or it ends in a
+    /// `:` or `->` still waiting for its body. Once a block has been opened,
+    /// the input also runs until a blank line, so the block can keep growing.
+    /// Input with any other scan error is complete, as more lines can't fix
+    /// it.
+    pub fn is_complete(&mut self, source: &str) -> bool {
+        let tokens = match self.try_scan_line(source) {
+            Ok(tokens) => tokens,
+            Err(ScanError::UnterminatedString { .. }) => return false,
+            Err(_) => return true,
+        };
+
+        let mut depth = 0;
+        for token in &tokens {
+            match token.token_type {
+                TokenType::LeftParen | TokenType::LeftBrace | TokenType::LeftBracket => depth += 1,
+                TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => {
+                    depth -= 1
+                }
+                _ => {}
+            }
+        }
+
+        let last = tokens.iter().rev().find(|token| {
+            !matches!(
+                token.token_type,
+                TokenType::Semicolon | TokenType::EndBlock | TokenType::EndOfFile
+            )
+        });
+        let open = last.is_some_and(|token| {
+            matches!(token.token_type, TokenType::Colon | TokenType::ThinArrow)
+        });
+        let in_block = tokens
+            .iter()
+            .any(|token| token.token_type == TokenType::BeginBlock);
+        let blank_line = source.ends_with('\n')
+            && source
+                .lines()
+                .last()
+                .is_none_or(|line| line.trim().is_empty());
+
+        depth <= 0 && !open && (!in_block || blank_line)
+    }
+
     pub fn parse(&mut self, tokens: Vec<Token>) -> Vec<Expr> {
         self.parser.parse(tokens)
     }
 
+    /// Finds undefined names and calls with the wrong number of arguments
+    /// without running `expressions`.
+    pub fn check(&self, expressions: &[Expr]) -> Vec<Diagnostic> {
+        checker::check(expressions)
+    }
+
     pub fn evaluate(&mut self, expressions: Vec<Expr>) -> Vec<Value> {
         self.interpreter.interpret(expressions)
     }
+
+    pub fn try_evaluate(&mut self, expressions: Vec<Expr>) -> Result<Vec<Value>, RuntimeError> {
+        self.interpreter.try_interpret(expressions)
+    }
+
+    /// Lets programs read and write files with `read_file!` and
+    /// `write_file!`. Off by default, so an embedded program can't touch the
+    /// file system unless allowed to.
+    pub fn allow_fs(&mut self, allow: bool) {
+        self.interpreter.allow_fs(allow)
+    }
+
+    /// Lets programs read environment variables with `env!`. Off by
+    /// default, like [`Compiler::allow_fs`].
+    pub fn allow_env(&mut self, allow: bool) {
+        self.interpreter.allow_env(allow)
+    }
+
+    /// Replaces what `sleep!` calls to pause, which is
+    /// [`std::thread::sleep`] by default.
+    pub fn set_sleeper(&mut self, sleeper: impl FnMut(Duration) + 'static) {
+        self.interpreter.set_sleeper(sleeper)
+    }
+
+    /// Logs each expression evaluated to `trace`, with the value it produced,
+    /// indented by how deeply the expression is nested.
+    pub fn with_trace(mut self, trace: impl Write + 'static) -> Self {
+        self.interpreter.set_trace(trace);
+        self
+    }
+
+    /// Records which lines of the programs evaluated from now on run, for
+    /// [`Compiler::coverage_report`].
+    pub fn enable_coverage(&mut self) {
+        self.interpreter.enable_coverage()
+    }
+
+    /// The lines that have run and not run since coverage was enabled, or an
+    /// empty report if it never was.
+    pub fn coverage_report(&self) -> CoverageReport {
+        let mut report = CoverageReport::default();
+        for (&line, &executed) in self.interpreter.coverage().into_iter().flatten() {
+            if executed {
+                report.executed.push(line);
+            } else {
+                report.missed.push(line);
+            }
+        }
+        report
+    }
+
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        self.interpreter.define_global(name, value)
+    }
+
+    /// Runs `source`, counting each top-level assertion as a test. A failing
+    /// assertion, or any other statement that errors, is recorded as a failure
+    /// on the statement's line and the rest of the file still runs.
+    pub fn run_tests(&mut self, source: &str) -> TestReport {
+        let tokens = self.scan_line(source);
+        let mut report = TestReport::default();
+        for expr in self.parse(tokens) {
+            let assertion = assertion_line(&expr);
+            let line = assertion
+                .or_else(|| expr.location().map(|location| location.line))
+                .unwrap_or_default();
+
+            match self.try_evaluate(vec![expr]) {
+                Ok(_) if assertion.is_some() => report.passed += 1,
+                Ok(_) => {}
+                Err(RuntimeError::AssertionFailed(message)) => {
+                    report.failures.push(TestFailure { line, message })
+                }
+                Err(err) => report.failures.push(TestFailure {
+                    line,
+                    message: err.to_string(),
+                }),
+            }
+        }
+        report
+    }
+}
+
+/// The line of a top-level `assert!` or `assert_eq!` call, or `None` for any
+/// other statement.
+fn assertion_line(expr: &Expr) -> Option<usize> {
+    let Expr::FunctionCall(callee, ..) = expr else {
+        return None;
+    };
+    match callee.as_ref() {
+        Expr::BuiltinFunction(Token {
+            token_type: TokenType::Assert | TokenType::AssertEq,
+            location,
+        }) => Some(location.line),
+        _ => None,
+    }
 }
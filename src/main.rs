@@ -1,23 +1,86 @@
-use std::io::stdout;
+use std::{
+    env,
+    io::{stdin, stdout, BufRead, Write},
+    mem,
+    panic::{catch_unwind, AssertUnwindSafe},
+};
 
-use zeal::Compiler;
+use zeal::{parser::Value, Compiler};
 
+/// Reads statements from stdin and prints the value of each. Lines are
+/// gathered until they form a complete statement, so blocks can span
+/// several of them. The last value printed is bound to `_`, and the value
+/// of the nth input to produce one to `_n`. The command-line arguments are in
+/// `args`.
 fn main() {
-    let mut stdout = stdout().lock();
-    let mut compiler = Compiler::new(&mut stdout);
-    let tokens = compiler.scan_line(
-        r#"
-        
-        "#,
-    );
-    let expr = compiler.parse(tokens);
-    compiler.evaluate(expr);
+    let mut output = stdout();
+    let mut compiler = Compiler::with_input(&mut output, stdin().lock());
+    compiler.allow_fs(true);
+    compiler.allow_env(true);
+    let args = env::args().skip(1).map(Value::from).collect();
+    compiler.define_global("args", Value::List(args));
+    let mut source = String::new();
+    let mut history = 0;
+    loop {
+        print!("{}", if source.is_empty() { "> " } else { "... " });
+        stdout().flush().expect("Failed to flush prompt");
+
+        let mut line = String::new();
+        if compiler.read_line(&mut line).expect("Failed to read line") == 0 {
+            break;
+        }
+        source.push_str(&line);
+        if !compiler.is_complete(&source) {
+            continue;
+        }
+
+        let tokens = match compiler.try_scan_line(&mem::take(&mut source)) {
+            Ok(tokens) => tokens,
+            Err(err) => {
+                eprintln!("{err}");
+                continue;
+            }
+        };
+        // The parser reports errors by panicking, which shouldn't end the session.
+        let Ok(exprs) = catch_unwind(AssertUnwindSafe(|| compiler.parse(tokens))) else {
+            continue;
+        };
+        match compiler.try_evaluate(exprs) {
+            Ok(values) => {
+                // Statements such as `print!` are unit, which isn't worth echoing.
+                if let Some(value) = values.last().filter(|value| **value != Value::Unit) {
+                    println!("{value}");
+                }
+                remember(&mut compiler, &mut history, values);
+            }
+            Err(err) => eprintln!("{err}"),
+        }
+    }
+}
+
+/// Binds the last of `values` to `_` and the next history variable. Input that
+/// produced no values leaves the history unchanged.
+fn remember<T: Write, I: BufRead>(
+    compiler: &mut Compiler<T, I>,
+    history: &mut usize,
+    mut values: Vec<Value>,
+) {
+    let Some(value) = values.pop() else {
+        return;
+    };
+    *history += 1;
+    compiler.define_global(&format!("_{history}"), value.clone());
+    compiler.define_global("_", value);
 }
 
 #[cfg(test)]
 pub mod test_main {
-    use std::io::stdout;
-    use zeal::{parser::Value, Compiler};
+    use std::{cell::RefCell, io::stdout, rc::Rc, time::Duration};
+    use zeal::{
+        parser::{Expr, Signature, Type, Value, Visitor},
+        Compiler, CoverageReport, Diagnostic, Frame, Location, RuntimeError, ScanError,
+        TestFailure, TestReport, TokenType,
+    };
 
     #[test]
     pub fn interprets_fizzbuzz() {
@@ -169,6 +232,43 @@ pub mod test_main {
         )
     }
 
+    #[test]
+    pub fn scopes_closures_by_declaration_order() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            count := 0
+            label := "global"
+            f := fn ->
+                bump := fn ->
+                    count = count + 1
+                count := 100
+                bump!
+                bump!
+                peek := ()
+                if true:
+                    peek = fn -> label
+                label := "local"
+                print! (peek!)
+                count
+            print! (f!)
+            print! count
+            odd := fn n -> if n == 0: false else: even! (n - 1)
+            even := fn n -> if n == 0: true else: odd! (n - 1)
+            print! (even! 10)
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[String(\"global\")]\n[Int(100)]\n[Int(2)]\n[Bool(true)]\n"
+        );
+    }
+
     #[test]
     pub fn interprets_function_closures() {
         let mut output = vec![];
@@ -228,4 +328,3722 @@ pub mod test_main {
             ]
         )
     }
+
+    #[test]
+    pub fn converts_values_to_and_from_primitives() {
+        assert_eq!(Value::from(5), Value::Int(5));
+        assert_eq!(Value::from(true), Value::Bool(true));
+        assert_eq!(Value::from("hi"), Value::String(Rc::from("hi")));
+        assert_eq!(
+            Value::from(String::from("hi")),
+            Value::String(Rc::from("hi"))
+        );
+
+        assert_eq!(i32::try_from(Value::Int(5)), Ok(5));
+        assert_eq!(bool::try_from(Value::Bool(false)), Ok(false));
+        assert_eq!(String::try_from(Value::from("hi")), Ok(String::from("hi")));
+
+        let err = i32::try_from(Value::Bool(true)).unwrap_err();
+        assert_eq!(err.expected, "Int");
+        assert_eq!(err.to_string(), "Type error: expected Int, found Bool");
+
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line("fn x -> x");
+        let expr = compiler.parse(tokens);
+        let lambda = compiler.evaluate(expr).pop().unwrap();
+        let err = i32::try_from(lambda).unwrap_err();
+        assert_eq!(err.to_string(), "Type error: expected Int, found Lambda");
+    }
+
+    #[test]
+    pub fn interprets_euclidean_modulo() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            -7 % 3
+            -7 %% 3
+            7 %% 3
+            7 %% -3
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res,
+            [Value::Int(-1), Value::Int(2), Value::Int(1), Value::Int(1)]
+        )
+    }
+
+    #[test]
+    pub fn interprets_division() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            6 / 2
+            7 / 2
+            7 // 2
+            -7 // 2
+            7 // -2
+            7.5 // 2
+            1.5 * 2
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res,
+            [
+                Value::Float(3.0),
+                Value::Float(3.5),
+                Value::Int(3),
+                Value::Int(-4),
+                Value::Int(-4),
+                Value::Float(3.0),
+                Value::Float(3.0)
+            ]
+        )
+    }
+
+    #[test]
+    pub fn interprets_comparison_chains() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            trace := fn x ->
+                print! x
+                x
+            1 < 2 < 3 |> print!
+            3 < 2 < 1 |> print!
+            1 <= 1 < 2 <= 2 |> print!
+            (trace! 1) < (trace! 2) < (trace! 3) |> print!
+            (trace! 3) < (trace! 2) < (trace! 1) |> print!
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[Bool(true)]\n[Bool(false)]\n[Bool(true)]\n[Int(1)]\n[Int(2)]\n[Int(3)]\n[Bool(true)]\n[Int(3)]\n[Int(2)]\n[Bool(false)]\n"
+        )
+    }
+
+    #[test]
+    pub fn exits_functions_from_comparison_chains() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            in_range := fn x ->
+                y := 0 < x? < 10
+                "after"
+            in_range! none
+            in_range! (some! 5)
+            clamp := fn x ->
+                0 < (if x > 9: return 9 else: x) < 10
+            clamp! 20
+            clamp! 5
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(res[1..3], [Value::Optional(None), Value::from("after")]);
+        assert_eq!(res[4..], [Value::Int(9), Value::Bool(true)]);
+
+        let tokens = compiler.scan_line("\n1 < \"a\" < 3");
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr).unwrap_err().to_string(),
+            "Type error: Cannot compare Int with String on line 2"
+        );
+    }
+
+    #[test]
+    pub fn interprets_match_guards() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            sign := fn x -> match x: 0 -> "zero"; n if n > 0 -> "pos"; _ -> "neg"
+            describe := fn x ->
+                match x:
+                    -1 -> "minus one"
+                    n if n > 100 -> "big"
+                    n if n > 0 -> n * 2
+                    _ -> "other"
+            print! (sign! 0)
+            print! (sign! 5)
+            print! (sign! -5)
+            print! (describe! 500)
+            print! (describe! 5)
+            print! (describe! -1)
+            print! (describe! -7)
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[String(\"zero\")]\n[String(\"pos\")]\n[String(\"neg\")]\n[String(\"big\")]\n[Int(10)]\n[String(\"minus one\")]\n[String(\"other\")]\n"
+        )
+    }
+
+    #[test]
+    #[should_panic(expected = "No match arm for Int(3)")]
+    pub fn errors_on_unmatched_value() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            match 3: 1 -> "one"; n if n > 5 -> "big"
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+    }
+
+    #[test]
+    pub fn interprets_destructured_parameters() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            apply := fn f x -> f! x
+            apply! (fn (a, b) -> a * b) (3, 4) |> print!
+            nested := fn (a, (b, c)) d -> a + b + c + d
+            nested! (1, (2, 3)) 4 |> print!
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[Int(12)]\n[Int(10)]\n")
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected a tuple of 2 elements to destructure, found Int(3)")]
+    pub fn errors_destructuring_non_tuple_argument() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            (fn (a, b) -> a * b)! 3
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+    }
+
+    #[test]
+    pub fn interprets_comparisons_across_types() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            1 < 1.5
+            2.5 >= 3
+            "apple" < "banana"
+            false < true
+            (1, 2) < (1, 3)
+            (2, "a") <= (1, "b")
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res,
+            [
+                Value::Bool(true),
+                Value::Bool(false),
+                Value::Bool(true),
+                Value::Bool(true),
+                Value::Bool(true),
+                Value::Bool(false)
+            ]
+        );
+
+        assert_eq!(
+            Value::Int(1).compare(&Value::from("a")),
+            Err(RuntimeError::TypeError(String::from(
+                "Cannot compare Int with String"
+            )))
+        );
+        assert!(Value::Float(f64::NAN).compare(&Value::Float(1.0)).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Type error: Cannot compare Int with String")]
+    pub fn errors_comparing_incomparable_values() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            1 < "a"
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+    }
+
+    #[test]
+    pub fn interprets_structural_equality() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            [1, 2, 3] == [1, 2, 3]
+            [1, 2] == [1, 2, 3]
+            [] == []
+            [[1], [2]] == [[1], [2]]
+            [[1], [2]] != [[1], [3]]
+            (1, [2, (3, "a")]) == (1, [2, (3, "a")])
+            (1, 2) != (1, 2, 3)
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res,
+            [
+                Value::Bool(true),
+                Value::Bool(false),
+                Value::Bool(true),
+                Value::Bool(true),
+                Value::Bool(true),
+                Value::Bool(true),
+                Value::Bool(true)
+            ]
+        );
+
+        assert_eq!(
+            Value::List(vec![Value::List(vec![Value::Int(1)].into())].into()),
+            Value::List(vec![Value::List(vec![Value::Int(1)].into())].into())
+        );
+        assert_ne!(
+            Value::List(vec![Value::Int(1)].into()),
+            Value::Tuple(vec![Value::Int(1)].into())
+        );
+    }
+
+    #[test]
+    pub fn errors_on_equality_across_types() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            1 == 1.0
+            2.5 != 2
+            [1] != [1, 2]
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res,
+            [Value::Bool(true), Value::Bool(true), Value::Bool(true)]
+        );
+
+        for (program, message) in [
+            (r#"1 == "1""#, "Cannot compare Int with String"),
+            (r#""a" != 'a'"#, "Cannot compare String with Char"),
+            ("true == 1", "Cannot compare Bool with Int"),
+            ("[1] == (1,)", "Cannot compare List with Tuple"),
+            ("{1} == {1: 1}", "Cannot compare Set with Dict"),
+            ("none == 0", "Cannot compare Optional with Int"),
+            (
+                "(fn -> 1) == (fn -> 1)",
+                "Cannot compare Lambda with Lambda",
+            ),
+        ] {
+            let tokens = compiler.scan_line(program);
+            let expr = compiler.parse(tokens);
+            assert_eq!(
+                compiler.try_evaluate(expr),
+                Err(RuntimeError::Located {
+                    line: 1,
+                    error: Box::new(RuntimeError::TypeError(String::from(message)))
+                }),
+                "{program}"
+            );
+        }
+
+        assert_ne!(Value::Int(1), Value::from("1"));
+        assert_eq!(
+            Value::Int(1).equals(&Value::from("1")),
+            Err(RuntimeError::TypeError(String::from(
+                "Cannot compare Int with String"
+            )))
+        );
+    }
+
+    #[test]
+    pub fn interprets_sets() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            a := {1, 2, 2, 3}
+            b := {3, 4, (1, "x")}
+            print! a
+            contains! a 2 |> print!
+            contains! a 5 |> print!
+            union! a b |> print!
+            intersect! a b |> print!
+            {2, 1} == {1, 2} |> print!
+            {1: "one", 2: "two"} == {2: "two", 1: "one"} |> print!
+            contains! {"k": 1} "k" |> print!
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[Set([Int(1), Int(2), Int(3)])]\n[Bool(true)]\n[Bool(false)]\n[Set([Int(1), Int(2), Int(3), Int(4), Tuple([Int(1), String(\"x\")])])]\n[Set([Int(3)])]\n[Bool(true)]\n[Bool(true)]\n[Bool(true)]\n"
+        )
+    }
+
+    #[test]
+    #[should_panic(expected = "Type error: Unhashable type: Lambda")]
+    pub fn errors_on_unhashable_set_member() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            {1, fn x -> x}
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+    }
+
+    #[test]
+    pub fn interprets_value_captures() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            x := 1
+            y := 100
+            by_ref := fn -> x
+            by_value := fn[x, y] offset -> x + y + offset
+            bump := fn[x] ->
+                x = x + 10
+                x
+            x = 2
+            y = 200
+            print! (by_ref!)
+            print! (by_value! 1000)
+            print! (bump!)
+            print! (bump!)
+            print! x
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[Int(2)]\n[Int(1101)]\n[Int(11)]\n[Int(21)]\n[Int(2)]\n"
+        )
+    }
+
+    #[test]
+    pub fn interprets_mutual_recursion() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            is_even := fn n -> if n == 0: true else: is_odd! (n - 1)
+            is_odd := fn n -> if n == 0: false else: is_even! (n - 1)
+            is_even! 10 |> print!
+            is_odd! 7 |> print!
+            is_even! 7 |> print!
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[Bool(true)]\n[Bool(true)]\n[Bool(false)]\n")
+    }
+
+    #[test]
+    #[should_panic(expected = "Undefined function \"is_odd\"")]
+    pub fn errors_calling_undefined_function() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            is_even := fn n -> if n == 0: true else: is_odd! (n - 1)
+            is_even! 1
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+    }
+
+    #[test]
+    pub fn interprets_repeat_until() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            i := 0
+            repeat:
+                print! i
+                i = i + 1
+            until true
+            repeat:
+                print! i
+                i = i + 1
+            until i >= 3
+            repeat: print! "once" until i > 0
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[Int(0)]\n[Int(1)]\n[Int(2)]\n[String(\"once\")]\n")
+    }
+
+    #[test]
+    pub fn interprets_loop_with_break() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            i := 0
+            loop:
+                i = i + 1
+                if i == 2: continue
+                if i > 3: break
+                print! i
+            while true:
+                break
+            print! i
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[Int(1)]\n[Int(3)]\n[Int(4)]\n")
+    }
+
+    #[test]
+    pub fn restores_scope_after_error() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            a := 1
+            if true:
+                a := 2
+                b := 3
+                print! a // 0
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::Located {
+                line: 6,
+                error: Box::new(RuntimeError::DivisionByZero)
+            })
+        );
+
+        let tokens = compiler.scan_line(
+            r#"
+            print! a
+            b := 4
+            print! b
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[Int(1)]\n[Int(4)]\n")
+    }
+
+    #[test]
+    pub fn interprets_try_catch() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            try: throw! "boom" catch e: print! e
+            fail := fn x ->
+                if true:
+                    print! "before"
+                    throw! x
+                    print! "after"
+                x
+            result := try:
+                fail! 42
+            catch e: e + 1
+            print! result
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[String(\"boom\")]\n[String(\"before\")]\n[Int(43)]\n"
+        )
+    }
+
+    #[test]
+    pub fn errors_on_uncaught_throw() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            try:
+                throw! "inner"
+            catch e:
+                throw! "outer"
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::Uncaught(Value::String(Rc::from("outer"))))
+        );
+    }
+
+    #[test]
+    pub fn interprets_optionals() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            add := fn x y -> x? + y?
+            print! (add! (some! 1) (some! 2))
+            print! (add! (some! 1) none)
+            print! (add! none (some! 2))
+            print! (some! 1) == (some! 1) none == none
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[Int(3)]\n[Optional(None)]\n[Optional(None)]\n[Bool(true), Bool(true)]\n"
+        )
+    }
+
+    #[test]
+    pub fn errors_propagating_non_optional() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            unwrap := fn x -> x?
+            unwrap! 1
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::Traced {
+                error: Box::new(RuntimeError::TypeError(String::from(
+                    "Cannot apply ? to Int"
+                ))),
+                trace: vec![Frame {
+                    function: String::from("unwrap"),
+                    line: 3
+                }],
+            })
+        );
+    }
+
+    #[test]
+    pub fn runs_assertions_as_tests() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let report = compiler.run_tests(
+            r#"
+            double := fn x -> x * 2
+            assert! (double! 2) == 4
+
+            assert! (double! 3) == 7 "doubles three"
+            assert! 1
+            assert! (double! 0) == 0
+            print! "done"
+            "#,
+        );
+
+        assert_eq!(
+            report,
+            TestReport {
+                passed: 2,
+                failures: vec![
+                    TestFailure {
+                        line: 5,
+                        message: String::from("doubles three")
+                    },
+                    TestFailure {
+                        line: 6,
+                        message: String::from("Type error: invalid arguments to assert: [Int(1)]")
+                    },
+                ],
+            }
+        );
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[String(\"done\")]\n")
+    }
+
+    #[test]
+    pub fn errors_on_stack_overflow() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            sum := fn n -> if n == 0: 0 else: n + sum! (n - 1)
+            print! (sum! 1000)
+            print! (sum! 30000)
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let tokens = compiler.scan_line("sum! 50000");
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::StackOverflow)
+        );
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[Int(500500)]\n[Int(450015000)]\n")
+    }
+
+    #[test]
+    pub fn interprets_tail_calls_in_constant_stack() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            count := fn n acc -> if n == 0: acc else: count! (n - 1) (acc + 1)
+            print! (count! 1000000 0)
+            first_even := fn n ->
+                if n % 2 == 0: return n
+                return first_even! (n + 1)
+            print! (first_even! 7)
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[Int(1000000)]\n[Int(8)]\n")
+    }
+
+    #[test]
+    pub fn runs_assert_eq() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let report = compiler.run_tests(
+            r#"
+            assert_eq! (1 + 1) 2
+            assert_eq! [1, 2] [1, 3]
+            assert_eq! (some! "a") none "wrapped"
+            "#,
+        );
+
+        assert_eq!(
+            report,
+            TestReport {
+                passed: 1,
+                failures: vec![
+                    TestFailure {
+                        line: 3,
+                        message: String::from("[1, 2] != [1, 3]")
+                    },
+                    TestFailure {
+                        line: 4,
+                        message: String::from("wrapped: some(\"a\") != none")
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    pub fn reports_statement_errors_as_test_failures() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let report = compiler.run_tests(
+            r#"
+            x := 1
+            y := missing + 1
+            assert! x == 1
+            throw! "boom"
+            assert_eq! x 1
+            "#,
+        );
+
+        assert_eq!(
+            report,
+            TestReport {
+                passed: 2,
+                failures: vec![
+                    TestFailure {
+                        line: 3,
+                        message: String::from("Undefined Variable \"missing\" on line 3")
+                    },
+                    TestFailure {
+                        line: 5,
+                        message: String::from("Uncaught exception: String(\"boom\")")
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    pub fn interprets_multi_line_call_arguments() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            apply := fn f x -> f! x
+            apply!
+                fn x ->
+                    y := x * 2
+                    y + 1
+                20
+            |> print!
+            add := fn a b -> a + b
+            add!
+                1 +
+                    2;
+                add!
+                    3
+                    4
+            |> print!
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[Int(41)]\n[Int(10)]\n")
+    }
+
+    #[test]
+    pub fn interprets_pipelines_into_methods_and_lambdas() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            sub := fn a b -> a - b
+            10 |> 3.sub |> print!
+            10 |> 3.sub! |> print!
+            5 |> (fn a -> a * 2) |> print!
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[Int(7)]\n[Int(7)]\n[Int(10)]\n")
+    }
+
+    #[test]
+    pub fn interprets_each() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            total := 0
+            each!
+                fn x ->
+                    total = total + x
+                [1, 2, 3]
+            print! total
+            each! print [1, "two"]
+            each! (fn (k, v) -> print! k v) {"a": 1}
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[Int(6)]\n[Int(1)]\n[String(\"two\")]\n[String(\"a\"), Int(1)]\n"
+        )
+    }
+
+    #[test]
+    pub fn interprets_list_ends() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            sum := fn xs -> if xs == []: 0 else: (first! xs) + (sum! (rest! xs))
+            print! (sum! [1, 2, 3])
+            print! (head! [4, 5]) (last! [4, 5]) (tail! [4, 5]) (rest! [6])
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let tokens = compiler.scan_line("last! []");
+        let expr = compiler.parse(tokens);
+        let err = compiler.try_evaluate(expr).unwrap_err();
+        assert_eq!(err.to_string(), "Cannot take last of an empty list");
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[Int(6)]\n[Int(4), Int(5), List([Int(5)]), List([])]\n"
+        );
+    }
+
+    #[test]
+    pub fn interprets_slices() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            xs := [1, 2, 3, 4]
+            print! (slice! xs 1 3) (slice! xs 2 2) (slice! xs 3 1)
+            print! (slice! xs (-2) 10) (slice! xs (-10) 1)
+            print! (slice! "héllo" 1 3) (slice! "héllo" (-3) (-1))
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[List([Int(2), Int(3)]), List([]), List([])]\n\
+             [List([Int(3), Int(4)]), List([Int(1)])]\n\
+             [String(\"él\"), String(\"ll\")]\n"
+        )
+    }
+
+    #[test]
+    pub fn interprets_index_of() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            print! (index_of! [1, (2, 3), 4] (2, 3)) (index_of! [1] 2)
+            print! (index_of! "héllo" "llo") (index_of! "héllo" "x")
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[Optional(Some(Int(1))), Optional(None)]\n[Optional(Some(Int(2))), Optional(None)]\n"
+        )
+    }
+
+    #[test]
+    pub fn interprets_chained_field_access() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            add := fn a b -> a + b
+            make := fn x -> {"inner": {"value": x}}
+            point := {"x": 1, "y": {"z": 2}}
+            print! point.x point.y.z
+            print! (make! 3).inner.value
+            print! point.y.z.add! 10
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let tokens = compiler.scan_line("point.y.w");
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::UndefinedField(String::from("w")))
+        );
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[Int(1), Int(2)]\n[Int(3)]\n[Int(12)]\n")
+    }
+
+    #[test]
+    pub fn interprets_string_affixes() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            print! (trim! "  hi ") (starts_with! "hello" "he") (ends_with! "hello" "he")
+            trim! 1
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::TypeError(String::from(
+                "invalid arguments to trim: [Int(1)]"
+            )))
+        );
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[String(\"hi\"), Bool(true), Bool(false)]\n")
+    }
+
+    #[test]
+    pub fn interprets_replace() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            print! (replace! "a-b-c" "-" "_") (replace! "abc" "" "_") (replace! "aaa" "aa" "b")
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[String(\"a_b_c\"), String(\"abc\"), String(\"ba\")]\n"
+        )
+    }
+
+    #[test]
+    pub fn interprets_chars() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            each! print (chars! "añ")
+            print! (to_list! "") (chars! "ab")
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[String(\"a\")]\n[String(\"ñ\")]\n[List([]), List([String(\"a\"), String(\"b\")])]\n"
+        )
+    }
+
+    #[test]
+    pub fn interprets_chars_and_single_quoted_strings() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            print! 'a' 'ab' "a"
+            print! 'a' == 'a' 'a' < 'b'
+            print! (to_int! 'a') (from_int! 955)
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let tokens = compiler.scan_line(r#"'a' == "a""#);
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::Located {
+                line: 1,
+                error: Box::new(RuntimeError::TypeError(String::from(
+                    "Cannot compare Char with String"
+                )))
+            })
+        );
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[Char('a'), String(\"ab\"), String(\"a\")]\n\
+             [Bool(true), Bool(true)]\n\
+             [Int(97), Char('λ')]\n"
+        )
+    }
+
+    #[test]
+    pub fn interprets_unicode_identifiers() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            café := 1
+            λ := fn x -> x + café
+            café = café + 1
+            print! (λ! 2) "ünïcode"
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[Int(4), String(\"ünïcode\")]\n")
+    }
+
+    #[test]
+    pub fn interprets_triple_quoted_strings() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler
+            .scan_line("text := \"\"\"first \"quoted\"\n\n  indented\"\"\"\nprint! text \"\"");
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        assert_eq!(
+            compiler
+                .try_scan_line("x := 1\ny := \"\"\"abc\n")
+                .unwrap_err(),
+            ScanError::UnterminatedString { line: 2 }
+        );
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[String(\"first \\\"quoted\\\"\\n\\n  indented\"), String(\"\")]\n"
+        )
+    }
+
+    #[test]
+    pub fn interprets_raw_strings() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            r := r"C:\temp\new"
+            print! r r"\d+"
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[String(\"C:\\\\temp\\\\new\"), String(\"\\\\d+\")]\n"
+        )
+    }
+
+    #[test]
+    pub fn converts_between_bools_and_ints() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            print! (to_int! true) (to_int! false) (to_bool! 0) (to_bool! -5)
+            print! (to_int! 1 < 2) + (to_int! 2 < 1) + (to_int! 3 < 4)
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[Int(1), Int(0), Bool(false), Bool(true)]\n[Int(2)]\n"
+        )
+    }
+
+    #[test]
+    pub fn interprets_imports() {
+        let dir = std::env::temp_dir().join("zeal_interprets_imports");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("helpers.zl"), "increment := fn x -> x + 1\n").unwrap();
+        std::fs::write(
+            dir.join("utils.zl"),
+            "import \"helpers.zl\"\ndouble_plus_one := fn x -> increment! (x * 2)\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("main.zl"),
+            "import \"utils.zl\"\nprint! (double_plus_one! 3) (increment! 1)\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("a.zl"), "import \"b.zl\"\n").unwrap();
+        std::fs::write(dir.join("b.zl"), "import \"a.zl\"\n").unwrap();
+
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        compiler.run(&dir.join("main.zl")).unwrap();
+
+        let tokens = compiler.scan_line(&format!("import {:?}", dir.join("a.zl")));
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::CyclicImport(
+                dir.join("a.zl")
+                    .canonicalize()
+                    .unwrap()
+                    .display()
+                    .to_string()
+            ))
+        );
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[Int(7), Int(2)]\n")
+    }
+
+    #[test]
+    pub fn interprets_modules() {
+        let dir = std::env::temp_dir().join("zeal_interprets_modules");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("math.zl");
+        std::fs::write(
+            &path,
+            "print! \"loading\"\nadd := fn a b -> a + b\nzero := 0\n",
+        )
+        .unwrap();
+
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(&format!(
+            r#"
+            m := import {path:?}
+            n := import {path:?}
+            print! (m.add! 1 2) n.zero ((m.add)! 2 3)
+            "#
+        ));
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let tokens = compiler.scan_line("add! 1 2");
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::Located {
+                line: 1,
+                error: Box::new(RuntimeError::UndefinedFunction("add".to_string()))
+            })
+        );
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[String(\"loading\")]\n[Int(3), Int(0), Int(5)]\n")
+    }
+
+    #[test]
+    pub fn interprets_while_else() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            countdown := fn n stop ->
+                while n > 0:
+                    if n == stop:
+                        print! "stopped" n
+                        break
+                    n = n - 1
+                else:
+                    print! "finished" n
+            countdown! 3 2
+            countdown! 3 4
+            while false: 1 else: print! "done"
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[String(\"stopped\"), Int(2)]\n[String(\"finished\"), Int(0)]\n[String(\"done\")]\n"
+        )
+    }
+
+    #[test]
+    pub fn interprets_labeled_break() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            i := 0
+            outer: while i < 3:
+                i = i + 1
+                j := 0
+                loop:
+                    j = j + 1
+                    if j == 2: continue outer
+                    if i == 3: break outer
+                    print! i j
+            print! "done" i
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[Int(1), Int(1)]\n[Int(2), Int(1)]\n[String(\"done\"), Int(3)]\n"
+        )
+    }
+
+    #[test]
+    #[should_panic(expected = "Undefined loop label \"outer\"")]
+    pub fn errors_on_undefined_loop_label() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line("loop: break outer");
+        compiler.parse(tokens);
+    }
+
+    #[test]
+    pub fn interprets_switch() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            describe := fn n ->
+                switch n:
+                    case 1: "one"
+                    case 1 + 1:
+                        print! "two"
+                        print! "block"
+                    default: "many"
+            print! (describe! 1) (describe! 3)
+            describe! 2
+            switch "c": case "a": print! "a"; case "b": print! "b"
+            print! (switch 'x': case 'x': "x"; default: "other")
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[String(\"one\"), String(\"many\")]\n[String(\"two\")]\n[String(\"block\")]\n[String(\"x\")]\n"
+        )
+    }
+
+    #[test]
+    pub fn interprets_guards() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            safe_div := fn a b ->
+                guard b != 0 else: return none
+                some! a / b
+            print! (safe_div! 6 3) (safe_div! 1 0)
+            i := 0
+            while i < 4:
+                i = i + 1
+                guard contains! [1, 3] i else:
+                    continue
+                print! i
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let tokens = compiler.scan_line(r#"guard 1 > 2 else: print! "fell through""#);
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::GuardFellThrough)
+        );
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[Optional(Some(Float(2.0))), Optional(None)]\n[Int(1)]\n[Int(3)]\n[String(\"fell through\")]\n"
+        )
+    }
+
+    #[test]
+    pub fn interprets_compound_assignment() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            i := 1
+            while i <= 15:
+                if i % 3 == 0 && i % 5 == 0:
+                    print! "fizzbuzz"
+                else if i % 5 == 0:
+                    print! "buzz"
+                else if i % 3 == 0:
+                    print! "fizz"
+                else:
+                    print! i
+                i += 1
+            n := 10
+            n -= 4
+            n *= 3 + 1
+            n /= 8
+            print! n
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[Int(1)]\n[Int(2)]\n[String(\"fizz\")]\n[Int(4)]\n[String(\"buzz\")]\n[String(\"fizz\")]\n[Int(7)]\n[Int(8)]\n[String(\"fizz\")]\n[String(\"buzz\")]\n[Int(11)]\n[String(\"fizz\")]\n[Int(13)]\n[Int(14)]\n[String(\"fizzbuzz\")]\n[Float(3.0)]\n"
+        )
+    }
+
+    #[test]
+    pub fn interprets_sum_and_product() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            print! (sum! [1, 2, 3]) (product! [1, 2, 3, 4]) (sum! []) (product! [])
+            print! (sum! [1, 2.5]) (product! [2.0, 3])
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let tokens = compiler.scan_line(r#"sum! [1, "two"]"#);
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::TypeError(String::from(
+                "Cannot take the sum of a list containing String"
+            )))
+        );
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[Int(6), Int(24), Int(0), Int(1)]\n[Float(3.5), Float(6.0)]\n"
+        )
+    }
+
+    #[test]
+    pub fn errors_on_integer_overflow() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        for source in [
+            "sum! [2147483647, 1]",
+            "product! [65536, 65536]",
+            "sum! [-2147483647, -2]",
+        ] {
+            let tokens = compiler.scan_line(source);
+            let expr = compiler.parse(tokens);
+            assert_eq!(
+                compiler.try_evaluate(expr),
+                Err(RuntimeError::IntegerOverflow),
+                "{source:?}"
+            );
+        }
+        for source in [
+            "2147483647 + 1",
+            "-2147483647 - 2",
+            "65536 * 65536",
+            "(-2147483647 - 1) // -1",
+            "(-2147483647 - 1) % -1",
+            "-(-2147483647 - 1)",
+        ] {
+            let tokens = compiler.scan_line(source);
+            let expr = compiler.parse(tokens);
+            assert_eq!(
+                compiler.try_evaluate(expr),
+                Err(RuntimeError::Located {
+                    line: 1,
+                    error: Box::new(RuntimeError::IntegerOverflow)
+                }),
+                "{source:?}"
+            );
+        }
+        assert_eq!(
+            RuntimeError::IntegerOverflow.to_string(),
+            "Integer overflow"
+        );
+
+        let tokens = compiler.scan_line("sum! [2147483647, 1.0]");
+        let expr = compiler.parse(tokens);
+        assert_eq!(compiler.evaluate(expr), [Value::Float(2147483648.0)]);
+    }
+
+    #[test]
+    pub fn interprets_count() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            print! (count! (fn x -> x % 2 == 0) [1, 2, 3, 4]) (count! (fn x -> true) [])
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let tokens = compiler.scan_line("count! (fn x -> x) [1]");
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::TypeError(String::from(
+                "Predicate of count returned Int, expected Bool"
+            )))
+        );
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[Int(2), Int(0)]\n")
+    }
+
+    #[test]
+    pub fn interprets_all_and_any() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            positive := fn x ->
+                print! x
+                x > 0
+            print! (all! positive [1, -2, 3])
+            print! (any! positive [-1, 2, 3])
+            print! (all! positive []) (any! positive [])
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[Int(1)]\n[Int(-2)]\n[Bool(false)]\n[Int(-1)]\n[Int(2)]\n[Bool(true)]\n[Bool(true), Bool(false)]\n"
+        )
+    }
+
+    #[test]
+    pub fn parses_trailing_commas() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            x := 1
+            print! [1, 2,] (1,) (1, 2,) {1, 2,} {"a": 1,}
+            print! ((fn [x,] -> x)!)
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[List([Int(1), Int(2)]), Tuple([Int(1)]), Tuple([Int(1), Int(2)]), Set([Int(1), Int(2)]), Dict([(String(\"a\"), Int(1))])]\n[Int(1)]\n"
+        )
+    }
+
+    #[test]
+    #[should_panic(expected = "Unexpected token")]
+    pub fn errors_on_doubled_comma() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line("[1,, 2]");
+        compiler.parse(tokens);
+    }
+
+    #[test]
+    pub fn interprets_degenerate_programs() {
+        for source in [
+            "",
+            "  \n\t\n  ",
+            "# only a comment",
+            "\n  # one\n# two\n",
+            ";",
+        ] {
+            let mut output = vec![];
+            let mut compiler = Compiler::new(&mut output);
+            let tokens = compiler.scan_line(source);
+            let expr = compiler.parse(tokens);
+            assert!(expr.is_empty(), "{source:?} parsed to {expr:?}");
+            assert!(compiler.evaluate(expr).is_empty());
+        }
+    }
+
+    #[test]
+    pub fn interprets_comments() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r##"
+            # Comments may go anywhere
+            x := 1 # after code
+            if x == 1:
+              # at any indentation
+                print! "#" x
+                    # even deeper
+            "##,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[String(\"#\"), Int(1)]\n")
+    }
+
+    #[test]
+    pub fn interprets_debug() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            debug! "hi"
+            debug! 1 [2.5] (some! 'c')
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "String(\"hi\")\nInt(1) List([Float(2.5)]) Optional(Some(Char('c')))\n"
+        )
+    }
+
+    #[test]
+    pub fn parses_type_annotations() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            x: Int := 5
+            names: List[String] := ["a"]
+            add := fn a: Int b -> Int -> a + b
+            print! (add! x 2) names
+            "#,
+        );
+        let exprs = compiler.parse(tokens);
+        let int = || Type {
+            name: String::from("Int"),
+            params: vec![],
+        };
+        let Expr::Declaration(_, Some(annotation), _) = &exprs[1] else {
+            panic!("Expected annotated declaration: {:?}", exprs[1])
+        };
+        assert_eq!(
+            *annotation,
+            Type {
+                name: String::from("List"),
+                params: vec![Type {
+                    name: String::from("String"),
+                    params: vec![],
+                }],
+            }
+        );
+        let Expr::Declaration(_, None, Some(lambda)) = &exprs[2] else {
+            panic!("Expected declaration: {:?}", exprs[2])
+        };
+        let Expr::Lambda(_, _, signature) = lambda.as_ref() else {
+            panic!("Expected lambda: {lambda:?}")
+        };
+        assert_eq!(
+            *signature,
+            Signature {
+                params: vec![Some(int()), None],
+                ret: Some(int()),
+            }
+        );
+        compiler.evaluate(exprs);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[Int(7), List([String(\"a\")])]\n")
+    }
+
+    #[test]
+    pub fn checks_names_and_arity() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            add := fn a b -> a + b
+            twice := fn f x -> f! (f! x)
+            total := add! 1 2
+            print! (add! totl 3)
+            print! (add! 1)
+            print! ((fn x -> x)! 1 2)
+            later := fn -> helper! 1
+            helper := fn x -> match x: y -> y + z
+            print! (contains! [1] 1) (twice! (fn x -> x) 1)
+            "#,
+        );
+        let exprs = compiler.parse(tokens);
+
+        assert_eq!(
+            compiler.check(&exprs),
+            vec![
+                Diagnostic {
+                    line: 5,
+                    message: String::from("Undefined variable \"totl\""),
+                },
+                Diagnostic {
+                    line: 6,
+                    message: String::from("\"add\" takes 2 arguments but was given 1"),
+                },
+                Diagnostic {
+                    line: 7,
+                    message: String::from("Lambda takes 1 arguments but was given 2"),
+                },
+                Diagnostic {
+                    line: 9,
+                    message: String::from("Undefined variable \"z\""),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    pub fn visits_function_calls() {
+        #[derive(Default)]
+        struct CallCounter {
+            calls: usize,
+        }
+
+        impl Visitor for CallCounter {
+            fn visit_call(&mut self, callee: &Expr, args: &[Expr]) {
+                self.calls += 1;
+                self.visit_expr(callee);
+                self.visit_exprs(args);
+            }
+        }
+
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            double := fn x -> x * 2
+            if true:
+                print! (double! (double! 1))
+            [1, 2] |> each! print
+            "#,
+        );
+        let exprs = compiler.parse(tokens);
+
+        let mut counter = CallCounter::default();
+        counter.visit_exprs(&exprs);
+        assert_eq!(counter.calls, 4);
+    }
+
+    #[test]
+    pub fn detects_incomplete_input() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        for source in [
+            "x := 1\n",
+            "if x: print! x\n",
+            "(1,\n 2)\n",
+            "if x:\n    print! x\n\n",
+            "\n",
+            "x & y\n",
+        ] {
+            assert!(compiler.is_complete(source), "{source:?}");
+        }
+        for source in [
+            "if x:\n",
+            "add := fn a b ->\n",
+            "if x:\n    print! x\n",
+            "[1,\n",
+            "print! \"abc\n",
+        ] {
+            assert!(!compiler.is_complete(source), "{source:?}");
+        }
+    }
+
+    #[test]
+    pub fn remembers_repl_history() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let mut history = 0;
+        for line in ["1 + 1", "x := 5; x * 2", "", "_ + _1", "_2 + _3"] {
+            let tokens = compiler.scan_line(line);
+            let expr = compiler.parse(tokens);
+            let values = compiler.evaluate(expr);
+            super::remember(&mut compiler, &mut history, values);
+        }
+        assert_eq!(history, 4);
+
+        let tokens = compiler.scan_line("(_, _1, _2, _3, _4)");
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.evaluate(expr),
+            [Value::Tuple(
+                vec![
+                    Value::Int(22),
+                    Value::Int(2),
+                    Value::Int(10),
+                    Value::Int(12),
+                    Value::Int(22),
+                ]
+                .into()
+            )]
+        );
+    }
+
+    #[test]
+    pub fn scans_crlf_line_endings() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let lf = "if true:\n    if false:\n        print! 1\n    print! 2\nprint! 3\n";
+        let crlf = lf.replace('\n', "\r\n");
+
+        let mut layout = |source: &str| {
+            compiler
+                .scan_line(source)
+                .into_iter()
+                .map(|token| (token.token_type, token.location.line, token.location.col))
+                .collect::<Vec<_>>()
+        };
+        let expected = layout(lf);
+        assert_eq!(layout(&crlf), expected);
+        assert_eq!(
+            expected
+                .iter()
+                .filter(|(token_type, _, _)| *token_type == TokenType::BeginBlock)
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    pub fn errors_on_long_literals() {
+        let mut output = vec![];
+        let mut compiler = Compiler::with_max_literal_length(&mut output, 8);
+        let tokens = compiler.scan_line(r#"("12345678", 12345678, 1234.567)"#);
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.evaluate(expr),
+            [Value::Tuple(
+                vec![
+                    Value::from("12345678"),
+                    Value::Int(12345678),
+                    Value::Float(1234.567),
+                ]
+                .into()
+            )]
+        );
+
+        for source in [
+            "\n\"123456789\"",
+            "\n123456789",
+            "\n1234.5678",
+            "\n\"\"\"1234\n5678\"\"\"",
+        ] {
+            assert_eq!(
+                compiler.try_scan_line(source).unwrap_err(),
+                ScanError::LiteralTooLong { line: 2, max: 8 },
+                "{source:?}"
+            );
+        }
+        assert_eq!(
+            ScanError::LiteralTooLong { line: 2, max: 8 }.to_string(),
+            "Literal starting on line 2 is longer than 8 bytes"
+        );
+
+        let mut compiler = Compiler::new(&mut output);
+        let long = "a".repeat(100_000);
+        assert!(compiler.try_scan_line(&format!("{long:?}")).is_ok());
+    }
+
+    #[test]
+    pub fn errors_on_ints_too_large() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line("(2147483647, 99999999999.5)");
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.evaluate(expr),
+            [Value::Tuple(
+                vec![Value::Int(2147483647), Value::Float(99999999999.5),].into()
+            )]
+        );
+
+        let error = ScanError::IntTooLarge {
+            line: 2,
+            literal: String::from("99999999999"),
+        };
+        assert_eq!(
+            compiler.try_scan_line("\nx := 99999999999").unwrap_err(),
+            error
+        );
+        assert_eq!(
+            error.to_string(),
+            "Integer 99999999999 on line 2 is too large"
+        );
+        assert!(compiler.is_complete("x := 99999999999\n"));
+    }
+
+    #[test]
+    pub fn interprets_arity() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            add := fn a b -> a + b
+            arity! add
+            arity! (fn -> 1)
+            arity! (fn (a, b) c -> c)
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(res[1..], [Value::Int(2), Value::Int(0), Value::Int(2)]);
+
+        let tokens = compiler.scan_line("arity! 1");
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::TypeError(String::from(
+                "invalid arguments to arity: [Int(1)]"
+            )))
+        );
+    }
+
+    #[test]
+    pub fn interprets_unit() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            x := ()
+            nothing := fn -> return ()
+            print! ()
+            () == ()
+            x == nothing!
+            {(): 1}
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res[3..],
+            [
+                Value::Bool(true),
+                Value::Bool(true),
+                Value::Dict(vec![(Value::Unit, Value::Int(1))].into())
+            ]
+        );
+        assert_eq!(Value::Unit.to_string(), "()");
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[Unit]\n");
+    }
+
+    #[test]
+    pub fn interprets_statements_as_unit() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        compiler.set_sleeper(|_| {});
+        let tokens = compiler.scan_line(
+            r#"
+            print! 1 2
+            debug! 1
+            each! print []
+            dump_env!
+            sleep! 0
+            if false: 1
+            if true:
+                1
+            while false: 1
+            repeat: 1 until true
+            loop: break
+            switch 1: case 2: 2
+            guard true else: return 1
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(res, vec![Value::Unit; 12]);
+    }
+
+    #[test]
+    pub fn interprets_operators_as_functions() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            xs := [1, 2, 3, 4]
+            reduce! (+) 0 xs
+            reduce! (*) 1 xs
+            reduce! (-) 10 xs
+            reduce! (fn acc x -> acc + x * x) 0 xs
+            (+)! 1 2
+            (==)! 1 1
+            (<)! 2 1
+            (//)! 7 2
+            op := (%)
+            op! 7 3
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res[1..],
+            [
+                Value::Int(10),
+                Value::Int(24),
+                Value::Int(0),
+                Value::Int(30),
+                Value::Int(3),
+                Value::Bool(true),
+                Value::Bool(false),
+                Value::Int(3),
+                Value::Builtin(String::from("%")),
+                Value::Int(1),
+            ]
+        );
+
+        let tokens = compiler.scan_line(r#"reduce! (+) 0 [1, "a"]"#);
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::TypeError(String::from(
+                "Cannot apply Plus to Int and String"
+            )))
+        );
+    }
+
+    #[test]
+    pub fn interprets_flip() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            pair := fn a b -> (a, b)
+            (flip! pair)! 1 2
+            (flip! (-))! 3 10
+            reduce! (flip! (fn x acc -> acc * 10 + x)) 0 [1, 2]
+            (flip! (flip! pair))! 1 2
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res[1..],
+            [
+                Value::Tuple(vec![Value::Int(2), Value::Int(1)].into()),
+                Value::Int(7),
+                Value::Int(12),
+                Value::Tuple(vec![Value::Int(1), Value::Int(2)].into()),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn interprets_memoize() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            calls := 0
+            fib := fn n ->
+                calls += 1
+                if n < 2: return n
+                (fib! (n - 1)) + (fib! (n - 2))
+            fib! 15
+            calls
+
+            calls = 0
+            fib = memoize! fib
+            fib! 15
+            calls
+            fib! 15
+            calls
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(res[2..4], [Value::Int(610), Value::Int(1973)]);
+        assert_eq!(
+            res[6..],
+            [
+                Value::Int(610),
+                Value::Int(16),
+                Value::Int(610),
+                Value::Int(16)
+            ]
+        );
+
+        let tokens = compiler.scan_line(
+            r#"
+            calls := 0
+            pair := fn x y ->
+                calls += 1
+                (x, y)
+            pair = memoize! pair
+            pair! 1 (some! "a")
+            pair! (some! "a") 1
+            pair! 1 (some! "a")
+            calls
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        assert_eq!(compiler.evaluate(expr)[6], Value::Int(2));
+
+        let tokens = compiler.scan_line("(memoize! (fn xs -> xs))! [1]");
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::TypeError(String::from(
+                "Unhashable type: List"
+            )))
+        );
+    }
+
+    #[test]
+    pub fn copies_collections_on_assignment() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            a := {"xs": [1, [2]], "inner": {"y": 2}}
+            b := a
+            b.xs = [3]
+            b.inner.y = 3
+            set_y := fn d ->
+                d.inner.y = 4
+                d
+            c := set_y! a
+            (a.xs, a.inner.y, b.xs, b.inner.y, c.inner.y)
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res.last(),
+            Some(&Value::Tuple(
+                vec![
+                    Value::List(
+                        vec![Value::Int(1), Value::List(vec![Value::Int(2)].into())].into()
+                    ),
+                    Value::Int(2),
+                    Value::List(vec![Value::Int(3)].into()),
+                    Value::Int(3),
+                    Value::Int(4),
+                ]
+                .into()
+            ))
+        );
+    }
+
+    #[test]
+    pub fn clones_modules() {
+        let dir = std::env::temp_dir().join("zeal_clones_modules");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("counter.zl");
+        std::fs::write(&path, "count := 0\nget := fn -> count\n").unwrap();
+
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(&format!(
+            r#"
+            m := import {path:?}
+            n := m
+            n.count = 1
+            c := clone! m
+            c.count = 2
+            copies := clone! [m, m]
+            first_copy := first! copies
+            first_copy.count = 3
+            (m.count, (m.get!), c.count, (c.get!), (last! copies).count)
+            "#
+        ));
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res.last(),
+            Some(&Value::Tuple(
+                vec![
+                    Value::Int(1),
+                    Value::Int(1),
+                    Value::Int(2),
+                    Value::Int(2),
+                    Value::Int(3),
+                ]
+                .into()
+            ))
+        );
+    }
+
+    #[test]
+    pub fn locates_runtime_errors() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        for (program, message) in [
+            (
+                "x := 1\n(x + \"a\")",
+                "Type error: Cannot apply Plus to Int and String on line 2",
+            ),
+            (
+                "\n\n-true",
+                "Type error: Cannot apply Minus to Bool on line 3",
+            ),
+            ("y := 1\ny + z", "Undefined Variable \"z\" on line 2"),
+            (
+                "f := 1\nf! 2",
+                "Type error: Int is not a function on line 2",
+            ),
+            (
+                "g := fn -> h! 1\n\ng!",
+                "Undefined function \"h\" on line 1\n  in g called on line 3",
+            ),
+            ("\n(5)! 1", "Type error: Int is not a function on line 2"),
+            ("\n\nw = 1", "Undefined Variable \"w\" on line 3"),
+            (
+                "d := 1\nd.a = 2",
+                "Type error: Cannot assign field \"a\" of Int on line 2",
+            ),
+        ] {
+            let tokens = compiler.scan_line(program);
+            let expr = compiler.parse(tokens);
+            let err = compiler.try_evaluate(expr).unwrap_err();
+            assert_eq!(err.to_string(), message);
+        }
+
+        // The parser rejects other targets, but an embedder can build them.
+        let tokens = compiler.scan_line("\nx := 1\nx = 2");
+        let mut expr = compiler.parse(tokens);
+        let Expr::Assignment(target, _, _) = &mut expr[1] else {
+            panic!("Expected an assignment, got {:?}", expr[1]);
+        };
+        **target = Expr::Literal(Value::Int(1));
+        assert_eq!(
+            compiler.try_evaluate(expr).unwrap_err().to_string(),
+            "Type error: Invalid LHS of assignment 1 on line 3"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid LHS of assignment on line 2")]
+    pub fn errors_on_invalid_assignment_target() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line("x := 1\n1 = x");
+        compiler.parse(tokens);
+    }
+
+    #[test]
+    pub fn parses_names_as_identifiers() {
+        #[derive(Default)]
+        struct Names {
+            identifiers: Vec<String>,
+            literals: Vec<Value>,
+        }
+
+        impl Visitor for Names {
+            fn visit_identifier(&mut self, name: &str, _location: Location) {
+                self.identifiers.push(name.to_string());
+            }
+
+            fn visit_literal(&mut self, value: &Value) {
+                self.literals.push(value.clone());
+            }
+        }
+
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            total := 0
+            add := fn a b -> a + b
+            total = add! total 1
+            reduce! (+) total [x]
+            "#,
+        );
+        let exprs = compiler.parse(tokens);
+
+        let mut names = Names::default();
+        names.visit_exprs(&exprs);
+        assert_eq!(
+            names.identifiers,
+            [
+                "total", "add", "a", "b", "a", "b", "total", "add", "total", "reduce", "+",
+                "total", "x"
+            ]
+        );
+        assert_eq!(names.literals, [Value::Int(0), Value::Int(1)]);
+    }
+
+    #[test]
+    pub fn interprets_field_assignment() {
+        let dir = std::env::temp_dir().join("zeal_interprets_field_assignment");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.zl");
+        std::fs::write(&path, "level := 1\nget_level := fn -> level\n").unwrap();
+
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(&format!(
+            r#"
+            point := {{"x": 1, "inner": {{"y": 2}}}}
+            copy := point
+            point.x = 10
+            point.inner.y += 5
+            point.z = 3
+            (point.x, point.inner.y, point.z, copy.x)
+
+            config := import {path:?}
+            config.level = 2
+            config.get_level!
+            "#
+        ));
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res[5],
+            Value::Tuple(vec![Value::Int(10), Value::Int(7), Value::Int(3), Value::Int(1)].into())
+        );
+        assert_eq!(res.last(), Some(&Value::Int(2)));
+
+        for (program, line, err) in [
+            (
+                "n := 1\nn.x = 2",
+                2,
+                RuntimeError::TypeError(String::from("Cannot assign field \"x\" of Int")),
+            ),
+            (
+                "config.missing = 1",
+                1,
+                RuntimeError::UndefinedField(String::from("missing")),
+            ),
+        ] {
+            let tokens = compiler.scan_line(program);
+            let expr = compiler.parse(tokens);
+            assert_eq!(
+                compiler.try_evaluate(expr),
+                Err(RuntimeError::Located {
+                    line,
+                    error: Box::new(err)
+                })
+            );
+        }
+    }
+
+    #[test]
+    pub fn interprets_collect_while() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            i := 0
+            collect while i < 3:
+                i += 1
+                i
+            i = 0
+            collect while true:
+                i += 1
+                if i > 6: break
+                if i % 2 == 0: continue
+                i * i
+            collect while false: 1
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        let list = |values: &[i32]| Value::List(values.iter().copied().map(Value::Int).collect());
+        assert_eq!(res[1], list(&[1, 2, 3]));
+        assert_eq!(res[3..], [list(&[1, 9, 25]), list(&[])]);
+    }
+
+    #[test]
+    pub fn interprets_list_comprehensions() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            xs := [1, 2, 3, 4, 5]
+            [x * 2 for x in xs]
+            [x for x in xs if x % 2 == 0]
+            [a + b for (a, b) in [(1, 2), (3, 4)]]
+            [[y for y in xs if y < x] for x in (1, 3)]
+            [x for x in [] if undefined]
+            "#,
+        );
+        let exprs = compiler.parse(tokens);
+        assert_eq!(
+            compiler.check(&exprs),
+            [Diagnostic {
+                line: 7,
+                message: String::from("Undefined variable \"undefined\""),
+            }]
+        );
+        let res = compiler.evaluate(exprs);
+        let list = |values: &[i32]| Value::List(values.iter().copied().map(Value::Int).collect());
+        assert_eq!(
+            res[1..],
+            [
+                list(&[2, 4, 6, 8, 10]),
+                list(&[2, 4]),
+                list(&[3, 7]),
+                Value::List(vec![list(&[]), list(&[1, 2])].into()),
+                list(&[]),
+            ]
+        );
+
+        let tokens = compiler.scan_line("[x for x in 5]");
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::TypeError(String::from(
+                "Cannot iterate over Int"
+            )))
+        );
+    }
+
+    #[test]
+    pub fn interprets_take_and_drop() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            xs := [1, 2, 3, 4, 1]
+            small := fn x -> x < 3
+            take! 2 xs
+            drop! 2 xs
+            take! 10 [1, 2, 3]
+            drop! 10 [1, 2, 3]
+            take! -1 xs
+            take_while! small xs
+            drop_while! small xs
+            take_while! small []
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        let list = |values: &[i32]| Value::List(values.iter().copied().map(Value::Int).collect());
+        assert_eq!(
+            res[2..],
+            [
+                list(&[1, 2]),
+                list(&[3, 4, 1]),
+                list(&[1, 2, 3]),
+                list(&[]),
+                list(&[]),
+                list(&[1, 2]),
+                list(&[3, 4, 1]),
+                list(&[]),
+            ]
+        );
+
+        let tokens = compiler.scan_line("drop_while! (fn x -> x) [1]");
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::TypeError(String::from(
+                "Predicate of drop_while returned Int, expected Bool"
+            )))
+        );
+    }
+
+    #[test]
+    pub fn interprets_flatten() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            flatten! [[1, 2], [], [3], [[4], 5]]
+            flatten! []
+            flat_map! (fn x -> [x, x * 10]) [1, 2]
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res,
+            [
+                Value::List(
+                    vec![
+                        Value::Int(1),
+                        Value::Int(2),
+                        Value::Int(3),
+                        Value::List(vec![Value::Int(4)].into()),
+                        Value::Int(5),
+                    ]
+                    .into()
+                ),
+                Value::List(vec![].into()),
+                Value::List(
+                    vec![Value::Int(1), Value::Int(10), Value::Int(2), Value::Int(20)].into()
+                ),
+            ]
+        );
+
+        for (program, message) in [
+            ("flatten! [[1], 2]", "Cannot flatten a list containing Int"),
+            (
+                "flat_map! (fn x -> x) [[1], 2]",
+                "Cannot flat_map a list containing Int",
+            ),
+        ] {
+            let tokens = compiler.scan_line(program);
+            let expr = compiler.parse(tokens);
+            assert_eq!(
+                compiler.try_evaluate(expr),
+                Err(RuntimeError::TypeError(String::from(message)))
+            );
+        }
+    }
+
+    #[test]
+    pub fn interprets_group_by() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            group_by! (fn x -> x % 2 == 0) [1, 2, 3, 4, 5]
+            group_by! (fn x -> x) []
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res,
+            [
+                Value::Dict(
+                    vec![
+                        (
+                            Value::Bool(false),
+                            Value::List(vec![Value::Int(1), Value::Int(3), Value::Int(5)].into())
+                        ),
+                        (
+                            Value::Bool(true),
+                            Value::List(vec![Value::Int(2), Value::Int(4)].into())
+                        ),
+                    ]
+                    .into()
+                ),
+                Value::Dict(vec![].into()),
+            ]
+        );
+
+        let tokens = compiler.scan_line("group_by! (fn x -> [x]) [1]");
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::TypeError(String::from(
+                "Unhashable type: List"
+            )))
+        );
+    }
+
+    #[test]
+    pub fn interprets_unique() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            unique! [3, 1, 3, 2, 1]
+            unique! [[1, 2], [1], [1, 2], []]
+            unique! []
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res,
+            [
+                Value::List(vec![Value::Int(3), Value::Int(1), Value::Int(2)].into()),
+                Value::List(
+                    vec![
+                        Value::List(vec![Value::Int(1), Value::Int(2)].into()),
+                        Value::List(vec![Value::Int(1)].into()),
+                        Value::List(vec![].into()),
+                    ]
+                    .into()
+                ),
+                Value::List(vec![].into()),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn interprets_named_functions() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            fn add! a b -> a + b
+            fn fact! n -> if n <= 1: 1 else: n * (fact! (n - 1))
+            add! 1 2
+            fact! 5
+            (fn x -> x * 2)! 4
+            fn pair! a b ->
+                fn x y -> (x, y)
+            (pair! 0 0)! 1 2
+            fn greet! -> "hello"
+            greet!
+            fn x y -> x
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        assert!(matches!(
+            &expr[0],
+            Expr::Declaration(name, None, Some(init))
+                if matches!(name.as_ref(), Expr::Identifier(name, _) if name == "add")
+                    && matches!(init.as_ref(), Expr::Lambda(params, _, _) if params.len() == 2)
+        ));
+        assert!(matches!(
+            &expr[7],
+            Expr::Declaration(name, None, Some(init))
+                if matches!(name.as_ref(), Expr::Identifier(name, _) if name == "greet")
+                    && matches!(init.as_ref(), Expr::Lambda(params, _, _) if params.is_empty())
+        ));
+        // Without the `!`, a lambda is anonymous whatever its parameters.
+        assert!(matches!(&expr[9], Expr::Lambda(params, _, _) if params.len() == 2));
+        assert!(compiler.check(&expr).is_empty());
+        let res = compiler.evaluate(expr);
+        assert_eq!(res[2..5], [Value::Int(3), Value::Int(120), Value::Int(8)]);
+        assert_eq!(
+            res[6],
+            Value::Tuple(vec![Value::Int(1), Value::Int(2)].into())
+        );
+        assert_eq!(res[8], Value::from("hello"));
+        assert!(matches!(res[9], Value::Lambda(..)));
+
+        let tokens = compiler.scan_line("x");
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr).unwrap_err().to_string(),
+            "Undefined Variable \"x\" on line 1"
+        );
+    }
+
+    #[test]
+    pub fn interprets_is_empty() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            is_empty! ""
+            is_empty! "a"
+            is_empty! []
+            is_empty! [1]
+            is_empty! {1}
+            is_empty! {1: 2}
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res,
+            [true, false, true, false, false, false].map(Value::Bool)
+        );
+
+        let tokens = compiler.scan_line("is_empty! 0");
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::TypeError(String::from(
+                "invalid arguments to is_empty: [Int(0)]"
+            )))
+        );
+    }
+
+    #[test]
+    pub fn interprets_string_repetition() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            "ab" * 3
+            "ab" * 0
+            "" * 2
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res,
+            ["ababab", "", ""].map(|string| Value::String(Rc::from(string)))
+        );
+
+        let tokens = compiler.scan_line(r#""ab" * -1"#);
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::Located {
+                line: 1,
+                error: Box::new(RuntimeError::TypeError(String::from(
+                    "Cannot repeat a string -1 times"
+                )))
+            })
+        );
+    }
+
+    #[test]
+    pub fn interprets_padding() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            pad_left! "5" 3 "0"
+            pad_right! "5" 3 " "
+            pad_left! "1234" 3 "0"
+            pad_right! "é" 2 "-"
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res,
+            ["005", "5  ", "1234", "é-"].map(|string| Value::String(Rc::from(string)))
+        );
+
+        for pad in ["", "ab"] {
+            let tokens = compiler.scan_line(&format!("pad_left! \"5\" 3 {pad:?}"));
+            let expr = compiler.parse(tokens);
+            assert_eq!(
+                compiler.try_evaluate(expr),
+                Err(RuntimeError::TypeError(format!(
+                    "Padding of pad_left must be a single character, got {pad:?}"
+                )))
+            );
+        }
+    }
+
+    #[test]
+    pub fn interprets_range() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            range! 0 3
+            range! 2 2
+            [i * i for i in range! (-1) 2]
+            each! print (range! 5 0)
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res,
+            [
+                Value::List(vec![Value::Int(0), Value::Int(1), Value::Int(2)].into()),
+                Value::List(vec![].into()),
+                Value::List(vec![Value::Int(1), Value::Int(0), Value::Int(1)].into()),
+                Value::Unit,
+            ]
+        );
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "[Int(5)]\n[Int(4)]\n[Int(3)]\n[Int(2)]\n[Int(1)]\n");
+    }
+
+    #[test]
+    pub fn interprets_range_steps() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            range! 1 10 2
+            range! 10 0 (-3)
+            range! 3 3 (-1)
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res,
+            [
+                Value::List([1, 3, 5, 7, 9].map(Value::Int).to_vec().into()),
+                Value::List([10, 7, 4, 1].map(Value::Int).to_vec().into()),
+                Value::List(vec![].into()),
+            ]
+        );
+
+        for (program, message) in [
+            ("range! 0 5 0", "Cannot step from 0 to 5 by 0"),
+            ("range! 0 5 (-1)", "Cannot step from 0 to 5 by -1"),
+            ("range! 5 0 1", "Cannot step from 5 to 0 by 1"),
+        ] {
+            let tokens = compiler.scan_line(program);
+            let expr = compiler.parse(tokens);
+            assert_eq!(
+                compiler.try_evaluate(expr),
+                Err(RuntimeError::TypeError(String::from(message)))
+            );
+        }
+    }
+
+    #[test]
+    pub fn interprets_dump_env() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            x := 1
+            name := "global"
+            f := fn x ->
+                y := [x]
+                z := x * 2
+                name := "local"
+                if true:
+                    dump_env!
+                later := 0
+                dump_env!
+            f! 2
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        let globals = "scope 1:\n  f = <fn>\n  name = \"global\"\n  x = 1\n";
+        assert_eq!(
+            output,
+            format!(
+                "scope 0:\n  name = \"local\"\n  x = 2\n  y = [2]\n  z = 4\n{globals}\
+                 scope 0:\n  later = 0\n  name = \"local\"\n  x = 2\n  y = [2]\n  z = 4\n{globals}"
+            )
+        );
+    }
+
+    #[test]
+    pub fn traces_runtime_errors() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            inner := fn x -> x // 0
+            middle := fn x -> (inner! x) + 1
+            outer := fn x -> [middle! x]
+            outer! 1
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let err = compiler.try_evaluate(expr).unwrap_err();
+        let RuntimeError::Traced { error, trace } = &err else {
+            panic!("Expected a traced error, got {err:?}")
+        };
+        assert_eq!(
+            **error,
+            RuntimeError::Located {
+                line: 2,
+                error: Box::new(RuntimeError::DivisionByZero)
+            }
+        );
+        assert_eq!(
+            trace
+                .iter()
+                .map(|frame| (frame.function.as_str(), frame.line))
+                .collect::<Vec<_>>(),
+            [("outer", 5), ("middle", 4), ("inner", 3)]
+        );
+        assert_eq!(
+            err.to_string(),
+            "Division by zero on line 2\n  in inner called on line 3\n  in middle called on line 4\n  in outer called on line 5"
+        );
+
+        // Frames are popped again once the error has been reported.
+        let tokens = compiler.scan_line("middle! 1");
+        let expr = compiler.parse(tokens);
+        let Err(RuntimeError::Traced { trace, .. }) = compiler.try_evaluate(expr) else {
+            panic!("Expected a traced error")
+        };
+        assert_eq!(trace.len(), 2);
+    }
+
+    #[test]
+    pub fn interprets_shared_list_slices() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            xs := [1, 2, 3, 4]
+            ys := rest! (rest! xs)
+            zs := slice! (drop! 1 xs) 1 (-1)
+            ys == [3, 4]
+            zs == [3]
+            [ys, first! ys, take! 1 ys, (rest! zs) == []]
+            xs
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        let list = |values: &[i32]| Value::List(values.iter().copied().map(Value::Int).collect());
+        assert_eq!(
+            res[3..],
+            [
+                Value::Bool(true),
+                Value::Bool(true),
+                Value::List(
+                    vec![list(&[3, 4]), Value::Int(3), list(&[3]), Value::Bool(true)].into()
+                ),
+                list(&[1, 2, 3, 4]),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn interprets_push() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            build := fn n acc -> if n == 0: acc else: build! (n - 1) (push! acc n)
+            xs := build! 3 []
+            ys := push! xs 4
+            zs := push! xs 5
+            front := push! (take! 1 xs) 6
+            [xs, ys, zs, front, push! (rest! ys) 7]
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        let list = |values: &[i32]| Value::List(values.iter().copied().map(Value::Int).collect());
+        assert_eq!(
+            res.last(),
+            Some(&Value::List(
+                vec![
+                    list(&[3, 2, 1]),
+                    list(&[3, 2, 1, 4]),
+                    list(&[3, 2, 1, 5]),
+                    list(&[3, 6]),
+                    list(&[2, 1, 4, 7]),
+                ]
+                .into()
+            ))
+        );
+    }
+
+    #[test]
+    pub fn interprets_bytes() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            b := bytes! "hé"
+            len! b
+            byte_at! b 0
+            to_list! b
+            to_string! b
+            b == (bytes! [104, 195, 169])
+            {b: 1}
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res,
+            [
+                Value::Bytes(vec![104, 195, 169].into()),
+                Value::Int(3),
+                Value::Int(104),
+                Value::List(vec![Value::Int(104), Value::Int(195), Value::Int(169)].into()),
+                Value::String(Rc::from("hé")),
+                Value::Bool(true),
+                Value::Dict(vec![(Value::Bytes(vec![104, 195, 169].into()), Value::Int(1))].into()),
+            ]
+        );
+        assert_eq!(res[0].to_string(), r#"b"h\xc3\xa9""#);
+
+        for (program, message) in [
+            ("bytes! [256]", "Byte 256 is out of range"),
+            (
+                "byte_at! (bytes! \"a\") 1",
+                "Index 1 is out of range for 1 bytes",
+            ),
+            (
+                "to_string! (bytes! [104, 255])",
+                "Bytes are not valid UTF-8 from index 1",
+            ),
+        ] {
+            let tokens = compiler.scan_line(program);
+            let expr = compiler.parse(tokens);
+            assert_eq!(
+                compiler.try_evaluate(expr),
+                Err(RuntimeError::TypeError(String::from(message)))
+            );
+        }
+    }
+
+    #[test]
+    pub fn interprets_file_access() {
+        let dir = std::env::temp_dir().join("zeal_interprets_file_access");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notes.txt");
+        let missing = dir.join("missing.txt");
+        let _ = std::fs::remove_file(&missing);
+
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let program = format!(
+            "write_file! {path:?} \"first second\"\nread_file! {path:?}\ntry: read_file! {missing:?} catch err: \"missing\""
+        );
+
+        let tokens = compiler.scan_line(&program);
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::Forbidden(String::from("write_file")))
+        );
+
+        compiler.allow_fs(true);
+        let tokens = compiler.scan_line(&program);
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.evaluate(expr)[1..],
+            [
+                Value::String(Rc::from("first second")),
+                Value::String(Rc::from("missing")),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn interprets_env() {
+        std::env::set_var("ZEAL_INTERPRETS_ENV", "set");
+        std::env::remove_var("ZEAL_INTERPRETS_ENV_UNSET");
+
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let program = r#"
+            env! "ZEAL_INTERPRETS_ENV"
+            env! "ZEAL_INTERPRETS_ENV_UNSET"
+            "#;
+
+        let tokens = compiler.scan_line(program);
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::Forbidden(String::from("env")))
+        );
+
+        compiler.allow_env(true);
+        let tokens = compiler.scan_line(program);
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.evaluate(expr),
+            [
+                Value::Optional(Some(Box::new(Value::String(Rc::from("set"))))),
+                Value::Optional(None),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn interprets_sleep() {
+        let mut output = vec![];
+        let slept = Rc::new(RefCell::new(vec![]));
+        let mut compiler = Compiler::new(&mut output);
+        let sleeps = slept.clone();
+        compiler.set_sleeper(move |duration| sleeps.borrow_mut().push(duration));
+        let tokens = compiler.scan_line("sleep! 100\nsleep! 0");
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let tokens = compiler.scan_line("sleep! (-1)");
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::TypeError(String::from(
+                "Cannot sleep for -1 milliseconds"
+            )))
+        );
+
+        assert_eq!(
+            *slept.borrow(),
+            [Duration::from_millis(100), Duration::from_millis(0)]
+        );
+    }
+
+    #[test]
+    pub fn interprets_json() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        // Zeal strings can't contain quotes, so the document comes from outside.
+        compiler.define_global(
+            "text",
+            Value::String(Rc::from(
+                r#"{"a": [1, -2.5e1, true, null], "b": {"c": "\u00e9\n"}}"#,
+            )),
+        );
+        let tokens = compiler.scan_line(
+            r#"
+            data := parse_json! text
+            data
+            to_json! data
+            (parse_json! (to_json! data)) == data
+            to_json! [(1, "x"), (some! {"k": none}), 'c', 2.0]
+            try: parse_json! "[1, 2" catch err: err
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res[1],
+            Value::Dict(
+                vec![
+                    (
+                        Value::String(Rc::from("a")),
+                        Value::List(
+                            vec![
+                                Value::Int(1),
+                                Value::Float(-25.0),
+                                Value::Bool(true),
+                                Value::Unit
+                            ]
+                            .into()
+                        )
+                    ),
+                    (
+                        Value::String(Rc::from("b")),
+                        Value::Dict(
+                            vec![(Value::String(Rc::from("c")), Value::String(Rc::from("é\n")))]
+                                .into()
+                        )
+                    ),
+                ]
+                .into()
+            )
+        );
+        assert_eq!(
+            res[2..],
+            [
+                Value::String(Rc::from(r#"{"a":[1,-25.0,true,null],"b":{"c":"é\n"}}"#)),
+                Value::Bool(true),
+                Value::String(Rc::from(r#"[[1,"x"],{"k":null},"c",2.0]"#)),
+                Value::String(Rc::from("Invalid JSON at character 5: Expected ','")),
+            ]
+        );
+
+        for (program, message) in [
+            ("to_json! [fn x -> x]", "Cannot convert Lambda to JSON"),
+            (
+                "to_json! {1: 2}",
+                "JSON object keys must be strings, not Int",
+            ),
+        ] {
+            let tokens = compiler.scan_line(program);
+            let expr = compiler.parse(tokens);
+            assert_eq!(
+                compiler.try_evaluate(expr),
+                Err(RuntimeError::TypeError(String::from(message)))
+            );
+        }
+    }
+
+    #[test]
+    pub fn rejects_deeply_nested_json() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+        compiler.define_global("deepest", Value::from(nested(512)));
+        compiler.define_global("too_deep", Value::from(nested(200_000)));
+        let tokens = compiler.scan_line(
+            r#"
+            len! (parse_json! deepest)
+            try: parse_json! too_deep catch err: err
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res,
+            [
+                Value::Int(1),
+                Value::from("Invalid JSON at character 512: Too deeply nested"),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn interprets_input() {
+        let mut output = vec![];
+        let mut compiler = Compiler::with_input(&mut output, "Ada\r\n36\n".as_bytes());
+        let tokens = compiler.scan_line(
+            r#"
+            name := input!
+            age := input!
+            print! name age
+            input!
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(res.last(), Some(&Value::Optional(None)));
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[Optional(Some(String(\"Ada\"))), Optional(Some(String(\"36\")))]\n"
+        );
+    }
+
+    #[test]
+    pub fn displays_nested_collections() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            [1, 2, 3]
+            {"a": 1, "b": [2.5, 'c']}
+            [("x", [some! "y", none]), {1}, [], {"k": {"n": true}}]
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res.iter().map(Value::to_string).collect::<Vec<_>>(),
+            [
+                r#"[1, 2, 3]"#,
+                r#"{"a": 1, "b": [2.5, 'c']}"#,
+                r#"[("x", [some("y"), none]), {1}, [], {"k": {"n": true}}]"#,
+            ]
+        );
+    }
+
+    #[test]
+    pub fn interprets_scan() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            scan! (+) 0 [1, 2, 3]
+            scan! (fn acc x -> acc * x) 1 []
+            (last! (scan! (+) 0 [1, 2, 3])) == (reduce! (+) 0 [1, 2, 3])
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res,
+            [
+                Value::List([0, 1, 3, 6].map(Value::Int).to_vec().into()),
+                Value::List(vec![Value::Int(1)].into()),
+                Value::Bool(true),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn short_circuits_logical_operators() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            calls := 0
+            touch := fn result ->
+                calls += 1
+                result
+            false && (touch! true)
+            true || (touch! false)
+            true && (touch! false)
+            false || (touch! true)
+            false || true && true
+            calls
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res[2..],
+            [false, true, false, true, true]
+                .map(Value::Bool)
+                .into_iter()
+                .chain([Value::Int(2)])
+                .collect::<Vec<_>>()
+        );
+
+        let tokens = compiler.scan_line("false && 1\n1 && false");
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr),
+            Err(RuntimeError::Located {
+                line: 2,
+                error: Box::new(RuntimeError::TypeError(String::from(
+                    "Cannot apply AndAnd to Int and Bool"
+                )))
+            })
+        );
+    }
+
+    #[test]
+    pub fn parses_operator_precedence_and_associativity() {
+        fn tree(expr: &Expr) -> String {
+            match expr {
+                Expr::Binary(lhs, op, rhs) => {
+                    format!("({:?} {} {})", op.token_type, tree(lhs), tree(rhs))
+                }
+                Expr::Unary(op, operand) => format!("({:?} {})", op.token_type, tree(operand)),
+                Expr::Group(expr) => format!("[{}]", tree(expr)),
+                Expr::Declaration(name, _, Some(init)) => {
+                    format!("(:= {} {})", tree(name), tree(init))
+                }
+                Expr::Identifier(name, _) => name.clone(),
+                Expr::Literal(Value::Int(value)) => value.to_string(),
+                expr => panic!("Unexpected expression {expr:?}"),
+            }
+        }
+
+        for (source, expected) in [
+            ("2 - 3 - 4", "(Minus (Minus 2 3) 4)"),
+            ("8 / 4 / 2", "(Slash (Slash 8 4) 2)"),
+            ("2 + 3 * 4", "(Plus 2 (Star 3 4))"),
+            ("2 * 3 % 4 // 5", "(SlashSlash (Mod (Star 2 3) 4) 5)"),
+            ("-2 * 3", "(Star (Minus 2) 3)"),
+            ("2 - -3", "(Minus 2 (Minus 3))"),
+            ("a < b == c", "(EqualEqual (Less a b) c)"),
+            ("a == b == c", "(EqualEqual (EqualEqual a b) c)"),
+            (
+                "a != b && c == d",
+                "(AndAnd (BangEqual a b) (EqualEqual c d))",
+            ),
+            ("!a && b", "(AndAnd (Bang a) b)"),
+            ("a || b && c", "(OrOr a (AndAnd b c))"),
+            ("a && b || c", "(OrOr (AndAnd a b) c)"),
+            ("a || b || c", "(OrOr (OrOr a b) c)"),
+            ("(a || b) && c", "(AndAnd [(OrOr a b)] c)"),
+            // Lines indented past the statement's first continue it
+            ("a\n    || b\n    && c", "(OrOr a (AndAnd b c))"),
+            ("a\n  && b\n    || c", "(OrOr (AndAnd a b) c)"),
+            ("x := a\n    || b\n  || c", "(:= x (OrOr (OrOr a b) c))"),
+            (
+                "x := a\n    + b * c\n    - d",
+                "(:= x (Minus (Plus a (Star b c)) d))",
+            ),
+            ("a ||\n  b", "(OrOr a b)"),
+        ] {
+            let mut output = vec![];
+            let mut compiler = Compiler::new(&mut output);
+            let tokens = compiler.scan_line(source);
+            let exprs = compiler.parse(tokens);
+            assert_eq!(exprs.len(), 1, "{source:?} parsed to {exprs:?}");
+            assert_eq!(tree(&exprs[0]), expected, "{source:?}");
+        }
+
+        // Continuation lines are relative to their own statement in a block
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line("if a:\n    x := b\n      || c\n    x && d\n      || e");
+        let Expr::If(_, body, None) = &compiler.parse(tokens)[0] else {
+            panic!("Expected if")
+        };
+        let Expr::Block(body) = body.as_ref() else {
+            panic!("Expected block")
+        };
+        assert_eq!(
+            body.iter().map(tree).collect::<Vec<_>>(),
+            ["(:= x (OrOr b c))", "(OrOr (AndAnd x d) e)"]
+        );
+    }
+
+    #[test]
+    pub fn interprets_if_then() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            x := 5
+            print! (if x > 3 then "big" else "small")
+            print! (if x > 9 then "big" else if x > 4 then "medium" else "small")
+            if x == 5 then print! "five" else print! "other"
+            print! (if (is_empty! []) then 1 else 2)
+            print! (if x < 3 then 1)
+            if x > 3: print! "colon" else: print! "other"
+            print! (if x > 3 then (if x > 4: 1 else: 2) else 3)
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[String(\"big\")]\n[String(\"medium\")]\n[String(\"five\")]\n[Int(1)]\n[Unit]\n[String(\"colon\")]\n[Int(1)]\n"
+        )
+    }
+
+    #[test]
+    pub fn shares_strings_between_copies() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            s := "abc" * 1000
+            id := fn x -> x
+            t := id! s
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let values = compiler.evaluate(expr);
+
+        let (Value::String(s), Value::String(t)) = (&values[0], &values[2]) else {
+            panic!("Expected strings: {values:?}")
+        };
+        assert!(Rc::ptr_eq(s, t));
+        assert_eq!(s.len(), 3000);
+    }
+
+    #[test]
+    pub fn interprets_hex_and_bin() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            print! (hex! 255) (bin! 10)
+            print! (hex! 0) (bin! 0)
+            print! (hex! (-255)) (bin! (-10))
+            print! (hex! 255 4) (bin! 5 8) (hex! (-1) 2) (hex! 4096 2) (bin! 1 (-3))
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[String(\"ff\"), String(\"1010\")]\n[String(\"0\"), String(\"0\")]\n[String(\"-ff\"), String(\"-1010\")]\n[String(\"00ff\"), String(\"00000101\"), String(\"-01\"), String(\"1000\"), String(\"1\")]\n"
+        )
+    }
+
+    #[test]
+    pub fn interprets_every_operator() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            [7 + 2, 7 - 2, 7 * 2, 7 / 2, 7 // 2, 7 % 2, 7 %% 2]
+            [7.5 + 2, 7.5 - 2, 7.5 * 2, 7.5 / 2, 7.5 // 2, -7.5 % 2, -7.5 %% 2]
+            [1 == 1, 1 != 1, 1 < 2, 1 <= 1, 1 > 2, 1 >= 2, true && false, true || false]
+            -(-2) // 1 # a comment, and the division before it
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let res = compiler.evaluate(expr);
+        assert_eq!(
+            res,
+            [
+                Value::List(
+                    [9, 5, 14]
+                        .map(Value::Int)
+                        .into_iter()
+                        .chain([Value::Float(3.5)])
+                        .chain([3, 1, 1].map(Value::Int))
+                        .collect()
+                ),
+                Value::List(
+                    [9.5, 5.5, 15.0, 3.75, 3.0, -1.5, 0.5]
+                        .map(Value::Float)
+                        .into_iter()
+                        .collect()
+                ),
+                Value::List(
+                    [true, false, true, true, false, false, false, true]
+                        .map(Value::Bool)
+                        .into_iter()
+                        .collect()
+                ),
+                Value::Int(2),
+            ]
+        );
+
+        for source in ["7 % 0", "7 %% 0", "7 // 0"] {
+            let tokens = compiler.scan_line(source);
+            let expr = compiler.parse(tokens);
+            assert_eq!(
+                compiler.try_evaluate(expr),
+                Err(RuntimeError::Located {
+                    line: 1,
+                    error: Box::new(RuntimeError::DivisionByZero)
+                }),
+                "{source:?}"
+            );
+        }
+
+        for (source, operator) in [
+            ("a & b", "&"),
+            ("a | b", "|"),
+            ("2 ** 3", "**"),
+            ("\nx => x", "=>"),
+        ] {
+            assert_eq!(
+                compiler.try_scan_line(source).unwrap_err().to_string(),
+                format!(
+                    "Unknown operator {operator} on line {}",
+                    source.lines().count()
+                ),
+            );
+        }
+    }
+
+    #[test]
+    pub fn converts_between_lists_and_tuples() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            print! (to_list! (1, 2, 3)) (to_tuple! [1, 2, 3])
+            print! (to_list! ()) (to_tuple! [])
+            print! (to_tuple! [1]) (to_list! [1]) (to_tuple! (1, 2))
+            pair := fn -> (3, 4)
+            print! [x * 2 for x in to_list! (pair!)]
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[List([Int(1), Int(2), Int(3)]), Tuple([Int(1), Int(2), Int(3)])]\n[List([]), Unit]\n[Tuple([Int(1)]), List([Int(1)]), Tuple([Int(1), Int(2)])]\n[List([Int(6), Int(8)])]\n"
+        )
+    }
+
+    #[test]
+    pub fn interprets_apply() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            apply! (fn a b -> a + b) [3, 4]
+            apply! (fn -> 1) []
+            apply! len [[1, 2]]
+            apply! (-) [10, 3]
+            apply! (flip! (-)) [10, 3]
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.evaluate(expr),
+            [
+                Value::Int(7),
+                Value::Int(1),
+                Value::Int(2),
+                Value::Int(7),
+                Value::Int(-7)
+            ]
+        );
+
+        let tokens = compiler.scan_line("apply! (fn a b -> a + b) [1, 2, 3]");
+        let expr = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(expr).unwrap_err().to_string(),
+            "Type error: Lambda takes 2 arguments but was given 3"
+        );
+    }
+
+    #[test]
+    pub fn forces_thunks_once() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            calls := 0
+            count := fn ->
+                calls = calls + 1
+                calls * 10
+            lazy := delay! count
+            copy := lazy
+            print! calls
+            print! (force! lazy) (force! copy) (force! lazy) calls
+            print! (lazy == copy) (lazy == (delay! count))
+            print! (force! (delay! (fn -> len! [1, 2])))
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        let values = compiler.evaluate(expr);
+        assert_eq!(values[2].to_string(), "<thunk>");
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[Int(0)]\n[Int(10), Int(10), Int(10), Int(1)]\n[Bool(true), Bool(false)]\n[Int(2)]\n"
+        )
+    }
+
+    #[test]
+    pub fn returns_several_values_as_a_tuple() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line(
+            r#"
+            divmod := fn a b -> (a // b, a %% b)
+            q, r := divmod! 17 5
+            print! q r
+            (x, (y, z)) := (1, divmod! (-7) 2)
+            print! x y z
+            swap := fn (a, b) -> (b, a)
+            first, second := swap! (divmod! 9 4)
+            print! first second
+            split := fn ->
+                lo, hi := (1, 2)
+                return (hi, lo)
+            print! (split!)
+            "#,
+        );
+        let exprs = compiler.parse(tokens);
+        assert_eq!(compiler.check(&exprs), []);
+        compiler.evaluate(exprs);
+
+        let tokens = compiler.scan_line("a, b := (1, 2, 3)");
+        let exprs = compiler.parse(tokens);
+        assert_eq!(
+            compiler.try_evaluate(exprs).unwrap_err().to_string(),
+            "Type error: Expected a tuple of 2 elements to destructure, found Tuple([Int(1), Int(2), Int(3)])"
+        );
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "[Int(3), Int(2)]\n[Int(1), Int(-4), Int(1)]\n[Int(1), Int(2)]\n[Tuple([Int(2), Int(1)])]\n"
+        )
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid LHS of declaration")]
+    pub fn errors_declaring_non_names() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        let tokens = compiler.scan_line("(a, 1) := (1, 2)");
+        compiler.parse(tokens);
+    }
+
+    #[test]
+    pub fn traces_evaluation() {
+        #[derive(Clone, Default)]
+        struct Trace(Rc<RefCell<Vec<u8>>>);
+
+        impl std::io::Write for Trace {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let trace = Trace::default();
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output).with_trace(trace.clone());
+        let tokens = compiler.scan_line(
+            r#"
+            double := fn x -> x * 2
+            if (double! (1 + 2)) > 5: "big"
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let lines = String::from_utf8(trace.0.take()).unwrap();
+        assert_eq!(
+            lines.lines().collect::<Vec<_>>(),
+            [
+                "double := ...",
+                "  fn x -> ...",
+                "  => <fn>",
+                "=> <fn>",
+                "if (double! (1 + 2)) > 5",
+                "  (double! (1 + 2)) > 5",
+                "    (double! (1 + 2))",
+                "      double! (1 + 2)",
+                "        (1 + 2)",
+                "          1 + 2",
+                "            1",
+                "            => 1",
+                "            2",
+                "            => 2",
+                "          => 3",
+                "        => 3",
+                "        x * 2",
+                "          x",
+                "          => 3",
+                "          2",
+                "          => 2",
+                "        => 6",
+                "      => 6",
+                "    => 6",
+                "    5",
+                "    => 5",
+                "  => true",
+                "  \"big\"",
+                "  => \"big\"",
+                "=> \"big\"",
+            ]
+        );
+
+        // A call in tail position is left for its caller to make.
+        let tokens = compiler.scan_line(
+            r#"
+            g := fn y -> y + 1
+            f := fn x -> g! x
+            f! 1
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        let trace = String::from_utf8(trace.0.take()).unwrap();
+        assert_eq!(
+            trace.lines().skip(8).collect::<Vec<_>>(),
+            [
+                "f! 1",
+                "  1",
+                "  => 1",
+                "  g! x",
+                "    x",
+                "    => 1",
+                "  <- tail call",
+                "  y + 1",
+                "    y",
+                "    => 1",
+                "    1",
+                "    => 1",
+                "  => 2",
+                "=> 2",
+            ]
+        );
+    }
+
+    #[test]
+    pub fn reports_line_coverage() {
+        let mut output = vec![];
+        let mut compiler = Compiler::new(&mut output);
+        assert_eq!(compiler.coverage_report(), CoverageReport::default());
+
+        compiler.enable_coverage();
+        let tokens = compiler.scan_line(
+            r#"
+            classify := fn n ->
+                if n < 0:
+                    print! "negative"
+                    -n
+                else:
+                    n * 2
+            classify! 3
+            unused := fn x ->
+                print! x
+            "#,
+        );
+        let expr = compiler.parse(tokens);
+        compiler.evaluate(expr);
+
+        assert_eq!(
+            compiler.coverage_report(),
+            CoverageReport {
+                executed: vec![2, 3, 7, 8, 9],
+                missed: vec![4, 5, 10],
+            }
+        );
+    }
 }
@@ -1,14 +1,27 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::{Cell, OnceCell, RefCell},
+    cmp::Ordering,
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+    iter, mem,
+    ops::{Index, Range},
+    rc::Rc,
+    slice,
+};
 
 use crate::{
-    interpreter::Environment,
-    scanner::{Token, TokenType},
+    interpreter::{Environment, RuntimeError, OPERATORS},
+    scanner::{Location, Token, TokenType},
 };
 
 pub struct Parser {
     tokens: Vec<Token>,
     index: usize,
-    col: usize,
+    /// Labels of the loops enclosing the current position.
+    labels: Vec<String>,
+    /// How many blocks enclose the current position.
+    depth: usize,
 }
 
 impl Parser {
@@ -16,15 +29,19 @@ impl Parser {
         Parser {
             tokens: Vec::default(),
             index: 0,
-            col: 0,
+            labels: Vec::default(),
+            depth: 0,
         }
     }
 
     pub fn parse(&mut self, tokens: Vec<Token>) -> Vec<Expr> {
         self.tokens = tokens;
+        self.index = 0;
+        self.labels.clear();
+        self.depth = 0;
         let mut res = Vec::default();
         while self.peek().is_some() {
-            while self.matches(vec![TokenType::LineEnd]) {}
+            while self.matches(vec![TokenType::LineEnd, TokenType::Semicolon]) {}
             if self.matches(vec![TokenType::EndOfFile]) {
                 break;
             }
@@ -95,13 +112,58 @@ impl Parser {
 
     fn block(&mut self) -> Expr {
         let mut res = Vec::default();
+        self.depth += 1;
         while !self.matches(vec![TokenType::EndBlock]) {
             res.push(self.statement());
         }
+        self.depth -= 1;
         Expr::Block(res)
     }
 
+    /// The label of a `label: while`, `label: repeat` or `label: loop` at the
+    /// current position.
+    fn loop_label(&mut self) -> Option<String> {
+        let token_type = |offset: usize| {
+            self.tokens
+                .get(self.index + offset)
+                .map(|token| &token.token_type)
+        };
+        match (token_type(0), token_type(1), token_type(2)) {
+            (
+                Some(TokenType::Identifier(label)),
+                Some(TokenType::Colon),
+                Some(TokenType::While | TokenType::Repeat | TokenType::Loop),
+            ) => Some(label.clone()),
+            _ => None,
+        }
+    }
+
+    /// The label a `break` or `continue` names, which must belong to an
+    /// enclosing loop.
+    fn jump_label(&mut self) -> Option<String> {
+        let Some(Token {
+            token_type: TokenType::Identifier(label),
+            ..
+        }) = self.peek()
+        else {
+            return None;
+        };
+        if !self.labels.contains(&label) {
+            panic!("Undefined loop label {label:?}")
+        }
+        self.advance();
+        Some(label)
+    }
+
     fn control_expression(&mut self) -> Expr {
+        if let Some(label) = self.loop_label() {
+            self.index += 2;
+            self.labels.push(label.clone());
+            let body = self.control_expression();
+            self.labels.pop();
+            return Expr::Labeled(label, Box::new(body));
+        }
+
         let Some(curr) = self.peek() else {
             panic!("Unexpected EOF")
         };
@@ -113,18 +175,146 @@ impl Parser {
                     panic!("Expected colon after while condition")
                 }
 
+                let body = if self.matches(vec![TokenType::BeginBlock]) {
+                    self.block()
+                } else {
+                    self.expression()
+                };
+
+                let else_branch = if self.matches(vec![TokenType::Else]) {
+                    if !self.matches(vec![TokenType::Colon]) {
+                        panic!("Expected colon after while else")
+                    }
+                    if self.matches(vec![TokenType::BeginBlock]) {
+                        Some(Box::new(self.block()))
+                    } else {
+                        Some(Box::new(self.expression()))
+                    }
+                } else {
+                    None
+                };
+
+                Expr::While(Box::new(cond), Box::new(body), else_branch)
+            }
+            TokenType::Collect => {
+                self.advance();
+                if !self.matches(vec![TokenType::While]) {
+                    panic!("Expected while after collect: {:?}", self.peek())
+                }
+                let cond = self.expression();
+                if !self.matches(vec![TokenType::Colon]) {
+                    panic!("Expected colon after collect while condition")
+                }
+
+                let body = if self.matches(vec![TokenType::BeginBlock]) {
+                    self.block()
+                } else {
+                    self.expression()
+                };
+                Expr::Collect(Box::new(cond), Box::new(body))
+            }
+            TokenType::Repeat => {
+                self.advance();
+                if !self.matches(vec![TokenType::Colon]) {
+                    panic!("Expected colon after repeat")
+                }
+
+                let body = if self.matches(vec![TokenType::BeginBlock]) {
+                    self.block()
+                } else {
+                    self.expression()
+                };
+
+                if !self.matches(vec![TokenType::Until]) {
+                    panic!("Expected until after repeat body: {:?}", self.peek())
+                }
+                Expr::Repeat(Box::new(body), Box::new(self.expression()))
+            }
+            TokenType::Loop => {
+                self.advance();
+                if !self.matches(vec![TokenType::Colon]) {
+                    panic!("Expected colon after loop")
+                }
+
                 if self.matches(vec![TokenType::BeginBlock]) {
-                    Expr::While(Box::new(cond), Box::new(self.block()))
+                    Expr::Loop(Box::new(self.block()))
+                } else {
+                    Expr::Loop(Box::new(self.expression()))
+                }
+            }
+            TokenType::Try => {
+                self.advance();
+                if !self.matches(vec![TokenType::Colon]) {
+                    panic!("Expected colon after try")
+                }
+
+                let body = if self.matches(vec![TokenType::BeginBlock]) {
+                    self.block()
                 } else {
-                    Expr::While(Box::new(cond), Box::new(self.expression()))
+                    self.expression()
+                };
+
+                if !self.matches(vec![TokenType::Catch]) {
+                    panic!("Expected catch after try body: {:?}", self.peek())
+                }
+                let Some(Token {
+                    token_type: TokenType::Identifier(name),
+                    ..
+                }) = self.peek()
+                else {
+                    panic!("Expected name after catch: {:?}", self.peek())
+                };
+                self.advance();
+                if !self.matches(vec![TokenType::Colon]) {
+                    panic!("Expected colon after catch name")
                 }
+
+                let handler = if self.matches(vec![TokenType::BeginBlock]) {
+                    self.block()
+                } else {
+                    self.expression()
+                };
+                Expr::Try(Box::new(body), name, Box::new(handler))
+            }
+            TokenType::Import => {
+                self.advance();
+                let TokenType::String(path) = self.advance().token_type else {
+                    panic!("Expected file path after import: {:?}", self.previous())
+                };
+                Expr::Module(path)
+            }
+            TokenType::Return => {
+                self.advance();
+                Expr::Return(Box::new(self.expression()))
+            }
+            TokenType::Break => {
+                self.advance();
+                Expr::Break(self.jump_label())
+            }
+            TokenType::Continue => {
+                self.advance();
+                Expr::Continue(self.jump_label())
             }
             TokenType::If => {
                 self.advance();
                 let cond = self.expression();
 
+                // `if cond then a else b` takes single expressions only.
+                if self.matches(vec![TokenType::Then]) {
+                    let if_branch = self.expression();
+                    let else_branch = if self.matches(vec![TokenType::Else]) {
+                        Some(Box::new(self.expression()))
+                    } else {
+                        None
+                    };
+                    return Expr::If(Box::new(cond), Box::new(if_branch), else_branch);
+                }
+
                 if !self.matches(vec![TokenType::Colon]) {
-                    panic!("Expected colon after if condition: {:?}", self.peek())
+                    panic!(
+                        "Expected colon or then after if condition: {:?}",
+                        self.peek()
+                    )
                 }
 
                 let if_branch = if self.matches(vec![TokenType::BeginBlock]) {
@@ -135,10 +325,11 @@ impl Parser {
 
                 let else_branch = if self.matches(vec![TokenType::Else]) {
                     if self.matches(vec![TokenType::Colon]) {
-                        if !self.matches(vec![TokenType::BeginBlock]) {
-                            panic!("Expected block after else")
+                        if self.matches(vec![TokenType::BeginBlock]) {
+                            Some(Box::new(self.block()))
+                        } else {
+                            Some(Box::new(self.expression()))
                         }
-                        Some(Box::new(self.block()))
                     } else {
                         Some(Box::new(self.expression()))
                     }
@@ -148,19 +339,200 @@ impl Parser {
 
                 Expr::If(Box::new(cond), Box::new(if_branch), else_branch)
             }
+            TokenType::Match => {
+                self.advance();
+                let scrutinee = self.expression();
+                if !self.matches(vec![TokenType::Colon]) {
+                    panic!("Expected colon after match value: {:?}", self.peek())
+                }
+
+                let mut arms = Vec::default();
+                if self.matches(vec![TokenType::BeginBlock]) {
+                    while !self.matches(vec![TokenType::EndBlock]) {
+                        arms.push(self.match_arm());
+                        if !self.matches(vec![TokenType::Semicolon])
+                            && !matches!(self.previous().token_type, TokenType::EndBlock)
+                        {
+                            panic!("Expected semicolon after match arm: {:?}", self.peek())
+                        }
+                    }
+                } else {
+                    arms.push(self.match_arm());
+                    while self.check(TokenType::Semicolon) && self.is_match_arm_start(1) {
+                        self.advance();
+                        arms.push(self.match_arm());
+                    }
+                }
+
+                Expr::Match(Box::new(scrutinee), arms)
+            }
+            TokenType::Guard => {
+                self.advance();
+                let cond = self.expression();
+                if !self.matches_all(vec![TokenType::Else, TokenType::Colon]) {
+                    panic!("Expected else: after guard condition: {:?}", self.peek())
+                }
+
+                let else_branch = if self.matches(vec![TokenType::BeginBlock]) {
+                    self.block()
+                } else {
+                    self.expression()
+                };
+                Expr::Guard(Box::new(cond), Box::new(else_branch))
+            }
+            TokenType::Switch => {
+                self.advance();
+                let scrutinee = self.expression();
+                if !self.matches(vec![TokenType::Colon]) {
+                    panic!("Expected colon after switch value: {:?}", self.peek())
+                }
+
+                let is_block = self.matches(vec![TokenType::BeginBlock]);
+                let mut cases = Vec::default();
+                let mut default = None;
+                loop {
+                    if is_block && self.matches(vec![TokenType::EndBlock]) {
+                        break;
+                    }
+
+                    if self.matches(vec![TokenType::Default]) {
+                        if default.is_some() {
+                            panic!("Duplicate default in switch")
+                        }
+                        default = Some(Box::new(self.switch_body()));
+                    } else if self.matches(vec![TokenType::Case]) {
+                        let value = self.logical_or();
+                        cases.push((value, self.switch_body()));
+                    } else {
+                        panic!("Expected case or default in switch: {:?}", self.peek())
+                    }
+
+                    // A call ending a case may already have consumed the
+                    // semicolon after it.
+                    let separated = self.matches(vec![TokenType::Semicolon])
+                        || matches!(
+                            self.previous().token_type,
+                            TokenType::Semicolon | TokenType::EndBlock
+                        );
+                    if is_block {
+                        if !separated && !self.check(TokenType::EndBlock) {
+                            panic!("Expected semicolon after switch case: {:?}", self.peek())
+                        }
+                    } else if !separated
+                        || !matches!(
+                            self.peek().map(|token| token.token_type),
+                            Some(TokenType::Case | TokenType::Default)
+                        )
+                    {
+                        break;
+                    }
+                }
+
+                Expr::Switch(Box::new(scrutinee), cases, default)
+            }
             _ => self.pipeline(),
         }
     }
 
+    fn switch_body(&mut self) -> Expr {
+        if !self.matches(vec![TokenType::Colon]) {
+            panic!("Expected colon after switch case: {:?}", self.peek())
+        }
+
+        if self.matches(vec![TokenType::BeginBlock]) {
+            self.block()
+        } else {
+            self.expression()
+        }
+    }
+
+    /// Whether the tokens `offset` ahead begin a `pattern ->` or `pattern if`
+    /// arm, used to find where a single line match ends.
+    fn is_match_arm_start(&mut self, offset: usize) -> bool {
+        let mut index = self.index + offset;
+        if matches!(
+            self.tokens.get(index).map(|t| &t.token_type),
+            Some(TokenType::Minus)
+        ) {
+            index += 1;
+        }
+        let is_pattern = matches!(
+            self.tokens.get(index).map(|t| &t.token_type),
+            Some(
+                TokenType::Identifier(_)
+                    | TokenType::Int(_)
+                    | TokenType::Float(_)
+                    | TokenType::String(_)
+                    | TokenType::Char(_)
+                    | TokenType::True
+                    | TokenType::False
+            )
+        );
+        is_pattern
+            && matches!(
+                self.tokens.get(index + 1).map(|t| &t.token_type),
+                Some(TokenType::ThinArrow | TokenType::If)
+            )
+    }
+
+    fn match_arm(&mut self) -> (Expr, Option<Expr>, Expr) {
+        let pattern = self.pattern();
+        let guard = if self.matches(vec![TokenType::If]) {
+            Some(self.logical_or())
+        } else {
+            None
+        };
+
+        if !self.matches(vec![TokenType::ThinArrow]) {
+            panic!("Expected -> after match pattern: {:?}", self.peek())
+        }
+
+        let body = if self.matches(vec![TokenType::BeginBlock]) {
+            self.block()
+        } else {
+            self.expression()
+        };
+        (pattern, guard, body)
+    }
+
+    fn pattern(&mut self) -> Expr {
+        if self.matches(vec![TokenType::Minus]) {
+            return match self.advance().token_type {
+                TokenType::Int(value) => Expr::Literal(Value::Int(-value)),
+                TokenType::Float(value) => Expr::Literal(Value::Float(-value)),
+                _ => panic!("Invalid pattern {:?}", self.previous()),
+            };
+        }
+
+        match self.primary() {
+            expr @ (Expr::Literal(_) | Expr::Identifier(..)) => expr,
+            expr => panic!("Invalid pattern {expr:?}"),
+        }
+    }
+
     fn statement(&mut self) -> Expr {
-        self.col = self.peek().expect("Should have token").location.col;
-        let mut expr = self.expression();
+        let mut expr = if self.depth == 0 && self.is_named_function() {
+            self.named_function()
+        } else {
+            self.expression()
+        };
+        // `a, b := value` declares several names from a tuple.
+        if matches!(expr, Expr::Identifier(..)) && self.check(TokenType::Comma) {
+            let mut names = vec![expr];
+            while self.matches(vec![TokenType::Comma]) {
+                names.push(self.primary());
+            }
+            if !self.check(TokenType::Colon) {
+                panic!("Expected := after names {names:?}")
+            }
+            expr = Expr::Tuple(names);
+        }
         match self.peek() {
             Some(Token {
                 token_type: TokenType::Colon,
                 ..
             }) => {
-                if !matches!(expr, Expr::Identifier(_)) {
+                if !Parser::is_binding(&expr) {
                     panic!("Invalid LHS of declaration {expr:?}")
                 }
                 expr = self.declaration(expr)
@@ -169,8 +541,21 @@ impl Parser {
                 token_type: TokenType::Equal,
                 ..
             }) => expr = self.assignment(expr),
+            Some(Token {
+                token_type:
+                    TokenType::PlusEqual
+                    | TokenType::MinusEqual
+                    | TokenType::StarEqual
+                    | TokenType::SlashEqual,
+                ..
+            }) => expr = self.compound_assignment(expr),
             _ => (),
         }
+        // A bare import brings the file's bindings into scope rather than
+        // evaluating to a module.
+        if let Expr::Module(path) = expr {
+            expr = Expr::Import(path);
+        }
         if !self.matches_over_line(TokenType::Semicolon)
             && !self.matches_over_line(TokenType::EndOfFile)
             && !matches!(
@@ -187,35 +572,182 @@ impl Parser {
         expr
     }
 
+    /// Whether the current position starts `fn name! params -> body`. The `!`
+    /// matches how the function is called, and can't follow a parameter of an
+    /// anonymous lambda.
+    fn is_named_function(&self) -> bool {
+        let token_type = |offset: usize| {
+            self.tokens
+                .get(self.index + offset)
+                .map(|token| &token.token_type)
+        };
+        matches!(
+            (token_type(0), token_type(1), token_type(2)),
+            (
+                Some(TokenType::Fn),
+                Some(TokenType::Identifier(_)),
+                Some(TokenType::Bang),
+            )
+        )
+    }
+
+    /// Parses `fn name! params -> body` as the declaration `name := fn params
+    /// -> body`.
+    fn named_function(&mut self) -> Expr {
+        self.advance();
+        let Token {
+            token_type: TokenType::Identifier(name),
+            location,
+        } = self.advance()
+        else {
+            unreachable!("Checked by is_named_function")
+        };
+        self.advance();
+        Expr::Declaration(
+            Box::new(Expr::Identifier(name, location)),
+            None,
+            Some(Box::new(self.function_decl())),
+        )
+    }
+
+    /// Whether `expr` can be declared: a name, or a tuple of names or of
+    /// nested tuples.
+    fn is_binding(expr: &Expr) -> bool {
+        match expr {
+            Expr::Identifier(..) => true,
+            Expr::Group(expr) => Parser::is_binding(expr),
+            Expr::Tuple(elements) => elements.iter().all(Parser::is_binding),
+            _ => false,
+        }
+    }
+
+    /// Parses `x := value`, or `x: Type := value` with a type annotation.
     fn declaration(&mut self, mut expr: Expr) -> Expr {
         if self.matches(vec![TokenType::Colon]) {
-            while !matches!(
-                self.peek(),
-                Some(Token {
-                    token_type: TokenType::Equal,
-                    ..
-                })
-            ) {
-                if self.peek().is_none() {
-                    panic!("Unexpected EOF");
+            let annotation = if self.check_identifier() {
+                let annotation = self.type_annotation();
+                if !self.matches(vec![TokenType::Colon]) {
+                    panic!("Expected := after type annotation: {:?}", self.peek())
                 }
-            }
-            if self.matches(vec![TokenType::Equal]) {
-                expr = Expr::Declaration(Box::new(expr), Some(Box::new(self.expression())))
+                Some(annotation)
             } else {
-                expr = Expr::Declaration(Box::new(expr), None)
-            }
+                None
+            };
+
+            let init = if self.matches(vec![TokenType::Equal]) {
+                Some(Box::new(self.expression()))
+            } else {
+                None
+            };
+            expr = Expr::Declaration(Box::new(expr), annotation, init)
         }
         expr
     }
 
+    fn check_identifier(&mut self) -> bool {
+        matches!(
+            self.peek(),
+            Some(Token {
+                token_type: TokenType::Identifier(_),
+                ..
+            })
+        )
+    }
+
+    /// Parses a type such as `Int` or `Dict[String, Int]`.
+    fn type_annotation(&mut self) -> Type {
+        let Token {
+            token_type: TokenType::Identifier(name),
+            ..
+        } = self.advance()
+        else {
+            panic!("Expected type name: {:?}", self.previous())
+        };
+
+        let mut params = Vec::default();
+        if self.matches(vec![TokenType::LeftBracket]) {
+            loop {
+                params.push(self.type_annotation());
+                if self.matches(vec![TokenType::RightBracket]) {
+                    break;
+                }
+                if !self.matches(vec![TokenType::Comma]) {
+                    panic!("Expected comma in type parameters: {:?}", self.peek())
+                }
+            }
+        }
+        Type { name, params }
+    }
+
+    /// Whether a lambda's `->` is followed by a return type and a second
+    /// `->`, as in `fn a: Int -> Int -> a`.
+    fn is_return_type(&mut self) -> bool {
+        let mut index = self.index;
+        let mut depth = 0;
+        loop {
+            match self.tokens.get(index).map(|token| &token.token_type) {
+                Some(TokenType::Identifier(_)) if index == self.index => {}
+                Some(TokenType::ThinArrow) if index > self.index && depth == 0 => return true,
+                Some(TokenType::LeftBracket) if index > self.index => depth += 1,
+                Some(TokenType::RightBracket) if depth > 0 => depth -= 1,
+                Some(TokenType::Identifier(_) | TokenType::Comma) if depth > 0 => {}
+                _ => return false,
+            }
+            index += 1;
+        }
+    }
+
     fn assignment(&mut self, mut expr: Expr) -> Expr {
         if self.matches(vec![TokenType::Equal]) {
-            expr = Expr::Assignment(Box::new(expr), Box::new(self.expression()))
+            let location = self.previous().location;
+            self.check_assignable(&expr);
+            expr = Expr::Assignment(Box::new(expr), Box::new(self.expression()), location)
         }
         expr
     }
 
+    /// Rejects assignment to anything but a name or a field of one, reporting
+    /// the line of the assignment operator just consumed.
+    fn check_assignable(&mut self, expr: &Expr) {
+        fn assignable(expr: &Expr) -> bool {
+            match expr {
+                Expr::Identifier(..) => true,
+                Expr::Get(obj, _) => assignable(obj),
+                _ => false,
+            }
+        }
+
+        if !assignable(expr) {
+            panic!(
+                "Invalid LHS of assignment on line {}: {expr:?}",
+                self.previous().location.line
+            )
+        }
+    }
+
+    /// Desugars `x += y` into `x = x + y`, and likewise for `-=`, `*=` and
+    /// `/=`.
+    fn compound_assignment(&mut self, expr: Expr) -> Expr {
+        let Token {
+            token_type,
+            location,
+        } = self.advance();
+        self.check_assignable(&expr);
+        let token_type = match token_type {
+            TokenType::PlusEqual => TokenType::Plus,
+            TokenType::MinusEqual => TokenType::Minus,
+            TokenType::StarEqual => TokenType::Star,
+            TokenType::SlashEqual => TokenType::Slash,
+            token_type => panic!("Invalid compound assignment {token_type:?}"),
+        };
+        let op = Token {
+            token_type,
+            location,
+        };
+        let value = Expr::Binary(Box::new(expr.clone()), op, Box::new(self.expression()));
+        Expr::Assignment(Box::new(expr), Box::new(value), location)
+    }
+
     fn expression(&mut self) -> Expr {
         self.control_expression()
     }
@@ -223,13 +755,28 @@ impl Parser {
     fn pipeline(&mut self) -> Expr {
         let mut expr = self.logical_or();
         while self.matches_over_line(TokenType::Pipeline) {
+            let location = self.previous().location;
             expr = match self.logical_or() {
-                Expr::FunctionCall(e, mut args) => {
+                // `x |> obj.method! a` is `method! x obj a`, as below
+                Expr::FunctionCall(mut e, mut args, call) => {
+                    if let Expr::Get(obj, name) = *e {
+                        args.insert(0, *obj);
+                        e = Box::new(Expr::Identifier(name, location));
+                    }
                     args.insert(0, expr);
-                    Expr::FunctionCall(e, args)
+                    Expr::FunctionCall(e, args, call)
                 }
-                Expr::Identifier(name) => {
-                    Expr::FunctionCall(Box::new(Expr::Identifier(name)), vec![expr])
+                callee @ Expr::Identifier(..) => {
+                    Expr::FunctionCall(Box::new(callee), vec![expr], location)
+                }
+                // `x |> obj.method` is `method! x obj`, matching `obj.method!`
+                Expr::Get(obj, name) => Expr::FunctionCall(
+                    Box::new(Expr::Identifier(name, location)),
+                    vec![expr, *obj],
+                    location,
+                ),
+                callee @ Expr::Group(_) => {
+                    Expr::FunctionCall(Box::new(callee), vec![expr], location)
                 }
                 _ => panic!("Expected function call in pipeline"),
             }
@@ -237,14 +784,12 @@ impl Parser {
         expr
     }
 
+    /// An operator leading a line indented deeper than its statement
+    /// continues the expression above it, since the scanner only ends a
+    /// statement at a line indented no deeper than its first.
     fn logical_or(&mut self) -> Expr {
         let mut expr = self.logical_and();
-        while self.matches(vec![TokenType::OrOr])
-            || self
-                .peek_next()
-                .map_or(false, |token| self.col < token.location.col)
-                && self.matches_all(vec![TokenType::LineEnd, TokenType::OrOr])
-        {
+        while self.matches(vec![TokenType::OrOr]) {
             let op = self.previous();
             let rhs = self.logical_and();
             expr = Expr::Binary(Box::new(expr), op, Box::new(rhs));
@@ -254,12 +799,7 @@ impl Parser {
 
     fn logical_and(&mut self) -> Expr {
         let mut expr = self.equality();
-        while self.matches(vec![TokenType::AndAnd])
-            || self
-                .peek_next()
-                .map_or(false, |token| self.col < token.location.col)
-                && self.matches_all(vec![TokenType::LineEnd, TokenType::AndAnd])
-        {
+        while self.matches(vec![TokenType::AndAnd]) {
             let op = self.previous();
             let rhs = self.equality();
             expr = Expr::Binary(Box::new(expr), op, Box::new(rhs));
@@ -278,7 +818,8 @@ impl Parser {
     }
 
     fn comparison(&mut self) -> Expr {
-        let mut expr = self.term();
+        let expr = self.term();
+        let mut chain = Vec::default();
         while self.matches(vec![
             TokenType::Greater,
             TokenType::GreaterEqual,
@@ -286,10 +827,17 @@ impl Parser {
             TokenType::LessEqual,
         ]) {
             let op = self.previous();
-            let rhs = self.term();
-            expr = Expr::Binary(Box::new(expr), op, Box::new(rhs));
+            chain.push((op, self.term()));
+        }
+
+        match chain.len() {
+            0 => expr,
+            1 => {
+                let (op, rhs) = chain.pop().expect("Should have comparison");
+                Expr::Binary(Box::new(expr), op, Box::new(rhs))
+            }
+            _ => Expr::Chain(Box::new(expr), chain),
         }
-        expr
     }
 
     fn term(&mut self) -> Expr {
@@ -309,6 +857,7 @@ impl Parser {
             TokenType::Slash,
             TokenType::SlashSlash,
             TokenType::Mod,
+            TokenType::ModMod,
         ]) {
             let op = self.previous();
             let rhs = self.unary();
@@ -328,7 +877,7 @@ impl Parser {
 
     fn call(&mut self) -> Expr {
         let mut expr = self.primary();
-        if matches!(expr, Expr::Lambda(_, _)) {
+        if matches!(expr, Expr::Lambda(..) | Expr::CaptureByValue(..)) {
             return expr;
         }
 
@@ -339,12 +888,11 @@ impl Parser {
                 };
                 expr = Expr::Get(Box::new(expr), name);
             } else if self.matches(vec![TokenType::Bang]) {
-                let mut args = self.arguments();
-                if let Expr::Get(lhs, name) = expr {
-                    expr = Expr::Identifier(name);
-                    args.insert(0, *lhs);
-                }
-                expr = Expr::FunctionCall(Box::new(expr), args);
+                let location = self.previous().location;
+                let args = self.arguments();
+                expr = Expr::FunctionCall(Box::new(expr), args, location);
+            } else if self.matches(vec![TokenType::Question]) {
+                expr = Expr::Propagate(Box::new(expr));
             } else {
                 break;
             }
@@ -355,29 +903,33 @@ impl Parser {
     fn arguments(&mut self) -> Vec<Expr> {
         let mut args = Vec::default();
         if self.matches(vec![TokenType::BeginBlock]) {
+            // Arguments may end in a nested block of their own, so separating
+            // semicolons are optional and repeated ones are skipped.
             while !self.matches(vec![TokenType::EndBlock]) {
-                args.push(self.logical_or());
-                if !self.matches(vec![TokenType::Semicolon])
-                    && !matches!(
-                        self.previous(),
-                        Token {
-                            token_type: TokenType::Semicolon,
-                            ..
-                        }
-                    )
-                {
-                    panic!(
-                        "Expected semicolon in function call block: {:?}",
-                        self.peek()
-                    )
+                if !self.matches(vec![TokenType::Semicolon]) {
+                    args.push(self.logical_or());
                 }
             }
         } else {
+            // A nested call as the last argument consumes the semicolon
+            // ending the line, which ends this call too.
             while !self.matches(vec![TokenType::Semicolon])
+                && !matches!(self.previous().token_type, TokenType::Semicolon)
                 && !matches!(
                     self.peek(),
                     Some(Token {
-                        token_type: TokenType::Pipeline,
+                        token_type: TokenType::Pipeline
+                            | TokenType::RightParen
+                            | TokenType::RightBracket
+                            | TokenType::RightBrace
+                            | TokenType::Comma
+                            | TokenType::Until
+                            | TokenType::Catch
+                            | TokenType::Then
+                            | TokenType::Else
+                            | TokenType::For
+                            | TokenType::In
+                            | TokenType::If,
                         ..
                     })
                 )
@@ -397,37 +949,179 @@ impl Parser {
             return Expr::Literal(Value::Bool(true));
         }
 
+        if self.matches(vec![TokenType::None]) {
+            return Expr::Literal(Value::Optional(None));
+        }
+
         match self.advance().token_type {
-            TokenType::String(value) => Expr::Literal(Value::String(value)),
-            TokenType::Identifier(value) => Expr::Identifier(value),
+            TokenType::String(value) => Expr::Literal(Value::String(value.into())),
+            TokenType::Char(value) => Expr::Literal(Value::Char(value)),
+            TokenType::Identifier(value) => Expr::Identifier(value, self.previous().location),
             TokenType::Int(value) => Expr::Literal(Value::Int(value)),
+            TokenType::Float(value) => Expr::Literal(Value::Float(value)),
+            TokenType::LeftParen if self.matches(vec![TokenType::RightParen]) => {
+                Expr::Literal(Value::Unit)
+            }
             TokenType::LeftParen => {
+                if let Some(operator) = self.operator_section() {
+                    return operator;
+                }
                 let expr = self.expression();
+                if self.check(TokenType::Comma) {
+                    let mut elements = vec![expr];
+                    while self.matches(vec![TokenType::Comma]) && !self.check(TokenType::RightParen)
+                    {
+                        elements.push(self.expression());
+                    }
+                    if !matches!(self.advance().token_type, TokenType::RightParen) {
+                        panic!("Unclosed tuple");
+                    }
+                    return Expr::Tuple(elements);
+                }
                 if !matches!(self.advance().token_type, TokenType::RightParen) {
                     panic!("Unclosed paren");
                 }
                 Expr::Group(Box::new(expr))
             }
-            TokenType::Plus => Expr::Identifier(String::from("+")),
+            TokenType::LeftBracket => {
+                let mut elements = Vec::default();
+                if !self.matches(vec![TokenType::RightBracket]) {
+                    loop {
+                        elements.push(self.expression());
+                        if elements.len() == 1 && self.matches(vec![TokenType::For]) {
+                            return self.comprehension(elements.remove(0));
+                        }
+                        if self.matches(vec![TokenType::RightBracket]) {
+                            break;
+                        }
+                        if !self.matches(vec![TokenType::Comma]) {
+                            panic!("Expected comma in list: {:?}", self.peek())
+                        }
+                        if self.matches(vec![TokenType::RightBracket]) {
+                            break;
+                        }
+                    }
+                }
+                Expr::List(elements)
+            }
+            TokenType::LeftBrace => self.braced_collection(),
+            TokenType::Plus => Expr::Identifier(String::from("+"), self.previous().location),
             TokenType::Fn => self.function_decl(),
-            TokenType::Print => Expr::BuiltinFunction(self.previous()),
+            TokenType::Print | TokenType::Assert | TokenType::AssertEq => {
+                Expr::BuiltinFunction(self.previous())
+            }
             _ => panic!("Unexpected token {:?}", self.previous()),
         }
     }
 
-    fn function_decl(&mut self) -> Expr {
-        let mut args = Vec::default();
-        while !self.matches(vec![TokenType::ThinArrow]) {
-            args.push(self.primary());
+    /// Parses the rest of `(op)`, which names a binary operator as a
+    /// function, or returns `None` if the parenthesis opens anything else.
+    fn operator_section(&mut self) -> Option<Expr> {
+        let op = self.peek()?;
+        let (name, _) = OPERATORS
+            .iter()
+            .find(|(_, token_type)| *token_type == op.token_type)?;
+        if !self
+            .peek_next()
+            .is_some_and(|token| token.token_type == TokenType::RightParen)
+        {
+            return None;
         }
+        self.advance();
+        self.advance();
+        Some(Expr::Identifier(String::from(*name), op.location))
+    }
 
-        if self.matches(vec![TokenType::BeginBlock]) {
-            let Expr::Block(exprs) = self.block() else {
-                panic!("Expected block")
-            };
-            Expr::Lambda(args, exprs)
-        } else {
-            Expr::Lambda(args, vec![self.expression()])
+    /// Parses the rest of `[body for pattern in iterable if guard]` after the
+    /// `for`. The guard is optional.
+    fn comprehension(&mut self, body: Expr) -> Expr {
+        let pattern = self.primary();
+        if !self.matches(vec![TokenType::In]) {
+            panic!("Expected in after comprehension pattern: {:?}", self.peek())
+        }
+        let iterable = self.expression();
+        let guard = self
+            .matches(vec![TokenType::If])
+            .then(|| Box::new(self.expression()));
+        if !self.matches(vec![TokenType::RightBracket]) {
+            panic!("Unclosed comprehension: {:?}", self.peek())
+        }
+        Expr::Comprehension(Box::new(body), Box::new(pattern), Box::new(iterable), guard)
+    }
+
+    /// Parses `{k: v, ...}` as a dict and `{a, b, ...}` as a set. Empty braces
+    /// are an empty dict. Like lists and tuples, either may end in a comma.
+    fn braced_collection(&mut self) -> Expr {
+        if self.matches(vec![TokenType::RightBrace]) {
+            return Expr::Dict(Vec::default());
+        }
+
+        let first = self.expression();
+        if self.matches(vec![TokenType::Colon]) {
+            let mut entries = vec![(first, self.expression())];
+            while self.matches(vec![TokenType::Comma]) && !self.check(TokenType::RightBrace) {
+                let key = self.expression();
+                if !self.matches(vec![TokenType::Colon]) {
+                    panic!("Expected colon after dict key: {:?}", self.peek())
+                }
+                entries.push((key, self.expression()));
+            }
+            if !self.matches(vec![TokenType::RightBrace]) {
+                panic!("Unclosed dict: {:?}", self.peek())
+            }
+            return Expr::Dict(entries);
+        }
+
+        let mut elements = vec![first];
+        while self.matches(vec![TokenType::Comma]) && !self.check(TokenType::RightBrace) {
+            elements.push(self.expression());
+        }
+        if !self.matches(vec![TokenType::RightBrace]) {
+            panic!("Unclosed set: {:?}", self.peek())
+        }
+        Expr::Set(elements)
+    }
+
+    fn function_decl(&mut self) -> Expr {
+        if self.matches(vec![TokenType::LeftBracket]) {
+            let mut captures = Vec::default();
+            while !self.matches(vec![TokenType::RightBracket]) {
+                let Token {
+                    token_type: TokenType::Identifier(name),
+                    ..
+                } = self.advance()
+                else {
+                    panic!("Expected name in capture list: {:?}", self.previous())
+                };
+                captures.push(name);
+                if !self.check(TokenType::RightBracket) && !self.matches(vec![TokenType::Comma]) {
+                    panic!("Expected comma in capture list: {:?}", self.peek())
+                }
+            }
+            return Expr::CaptureByValue(captures, Box::new(self.function_decl()));
+        }
+
+        let mut args = Vec::default();
+        let mut signature = Signature::default();
+        while !self.matches(vec![TokenType::ThinArrow]) {
+            args.push(self.primary());
+            signature.params.push(
+                self.matches(vec![TokenType::Colon])
+                    .then(|| self.type_annotation()),
+            );
+        }
+        if self.is_return_type() {
+            signature.ret = Some(self.type_annotation());
+            self.advance();
+        }
+
+        if self.matches(vec![TokenType::BeginBlock]) {
+            let Expr::Block(exprs) = self.block() else {
+                panic!("Expected block")
+            };
+            Expr::Lambda(args.into(), exprs.into(), signature)
+        } else {
+            Expr::Lambda(args.into(), Rc::from([self.expression()]), signature)
         }
     }
 }
@@ -438,39 +1132,963 @@ impl Default for Parser {
     }
 }
 
+/// Collections are values, not references: assigning or passing one copies
+/// it, and nothing changes one in place, so aliasing is never observable.
+/// That lets strings, collections and bytes share their contents between
+/// copies, making a copy cheap however large they are. Lambdas, modules and
+/// natives share their state between copies, so setting a field of a module
+/// shows through every copy of it. `clone!` copies modules too.
 #[derive(Clone, Debug)]
 pub enum Value {
-    String(String),
+    String(Rc<str>),
+    Char(char),
     Int(i32),
+    Float(f64),
     Bool(bool),
-    Lambda(Vec<Expr>, Vec<Expr>, Rc<RefCell<Environment>>),
+    Tuple(Rc<[Value]>),
+    List(List),
+    Set(Rc<[Value]>),
+    Dict(Rc<[(Value, Value)]>),
+    Optional(Option<Box<Value>>),
+    /// Parameters, body, the scope the lambda was made in and how many of
+    /// that scope's bindings had been declared by then, which are all it sees.
+    Lambda(Rc<[Expr]>, Rc<[Expr]>, Rc<RefCell<Environment>>, usize),
+    Builtin(String),
+    /// The top-level bindings of an imported file.
+    Module(Rc<RefCell<Environment>>),
+    /// The value of `()`, standing for nothing in particular.
+    Unit,
+    /// A function a builtin made out of another one.
+    Native(Rc<Native>),
+    /// Binary data, made by `bytes!`.
+    Bytes(Rc<[u8]>),
+    /// A function `delay!` wrapped to be called by `force!` at most once,
+    /// and the value it returned once it has been. Copies share the result.
+    Thunk(Box<Value>, Rc<RefCell<Option<Value>>>),
+}
+
+#[derive(Debug)]
+pub enum Native {
+    /// Calls the function with its first two arguments swapped.
+    Flip(Value),
+    /// Calls the function, remembering the result for each list of
+    /// arguments it has seen.
+    Memoize(Value, RefCell<HashMap<Vec<Key>, Value>>),
 }
 
+/// The elements of a list value. Copies share one buffer, and so do the
+/// slices `rest!`, `slice!`, `take!` and `drop!` make, so none of them copy
+/// any elements. The buffer only grows: `push!` fills its next free slot when
+/// the list ends where the filled slots do, and otherwise copies the list into
+/// a new buffer with room to spare. That's safe because a filled slot never
+/// changes.
+#[derive(Clone)]
+pub struct List {
+    buffer: Rc<Buffer>,
+    start: usize,
+    end: usize,
+}
+
+/// Slots for the elements of lists, filled in order.
+struct Buffer {
+    slots: Box<[OnceCell<Value>]>,
+    /// How many slots have been filled.
+    filled: Cell<usize>,
+}
+
+impl Buffer {
+    /// A buffer holding `elements`, with room for `capacity` in all.
+    fn new(elements: impl IntoIterator<Item = Value>, capacity: usize) -> Buffer {
+        let mut slots = Vec::with_capacity(capacity);
+        slots.extend(elements.into_iter().map(OnceCell::from));
+        let filled = slots.len();
+        slots.resize_with(capacity.max(filled), OnceCell::new);
+        Buffer {
+            slots: slots.into_boxed_slice(),
+            filled: Cell::new(filled),
+        }
+    }
+}
+
+/// The elements of a list, in order.
+pub type Iter<'a> = iter::Map<slice::Iter<'a, OnceCell<Value>>, fn(&OnceCell<Value>) -> &Value>;
+
+fn filled(slot: &OnceCell<Value>) -> &Value {
+    slot.get().expect("Slots in a list are filled")
+}
+
+impl List {
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        self.buffer.slots[self.start..self.end].iter().map(filled)
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Value> {
+        (index < self.len()).then(|| &self[index])
+    }
+
+    pub fn first(&self) -> Option<&Value> {
+        self.get(0)
+    }
+
+    pub fn last(&self) -> Option<&Value> {
+        self.len().checked_sub(1).and_then(|index| self.get(index))
+    }
+
+    pub fn contains(&self, value: &Value) -> bool {
+        self.iter().any(|element| element == value)
+    }
+
+    pub fn to_vec(&self) -> Vec<Value> {
+        self.iter().cloned().collect()
+    }
+
+    /// The elements in `range`, indexed from the start of this list.
+    pub fn slice(&self, range: Range<usize>) -> List {
+        assert!(range.start <= range.end && range.end <= self.len());
+        List {
+            buffer: self.buffer.clone(),
+            start: self.start + range.start,
+            end: self.start + range.end,
+        }
+    }
+
+    /// This list with `value` added to the end. Only the first list to push
+    /// onto the end of a buffer's filled slots gets to fill the next one, so
+    /// copies pushing different values don't see each other's.
+    pub fn push(&self, value: Value) -> List {
+        let buffer = &self.buffer;
+        if self.end == buffer.filled.get() && self.end < buffer.slots.len() {
+            buffer.slots[self.end]
+                .set(value)
+                .expect("Slots past the filled ones are empty");
+            buffer.filled.set(self.end + 1);
+            return List {
+                buffer: buffer.clone(),
+                start: self.start,
+                end: self.end + 1,
+            };
+        }
+
+        let capacity = (self.len() * 2).max(4);
+        List {
+            buffer: Rc::new(Buffer::new(self.iter().cloned().chain([value]), capacity)),
+            start: 0,
+            end: self.len() + 1,
+        }
+    }
+}
+
+impl Index<usize> for List {
+    type Output = Value;
+
+    fn index(&self, index: usize) -> &Value {
+        assert!(index < self.len(), "Index {index} out of bounds of a list");
+        filled(&self.buffer.slots[self.start + index])
+    }
+}
+
+impl From<Vec<Value>> for List {
+    fn from(elements: Vec<Value>) -> Self {
+        let len = elements.len();
+        List {
+            buffer: Rc::new(Buffer::new(elements, len)),
+            start: 0,
+            end: len,
+        }
+    }
+}
+
+impl FromIterator<Value> for List {
+    fn from_iter<I: IntoIterator<Item = Value>>(iter: I) -> Self {
+        List::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+impl<'a> IntoIterator for &'a List {
+    type Item = &'a Value;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl fmt::Debug for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// Structural equality, used for set membership, dict keys and patterns.
+/// Values of different types are never equal; `==` in a program goes
+/// through [`Value::equals`] instead, which rejects such pairings.
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Value::Int(x), Value::Int(other)) => x == other,
+            (Value::Float(x), Value::Float(other)) => x == other,
             (Value::Bool(x), Value::Bool(other)) => x == other,
             (Value::String(x), Value::String(other)) => x == other,
+            (Value::Char(x), Value::Char(other)) => x == other,
+            (Value::Tuple(x), Value::Tuple(other)) => x == other,
+            (Value::List(x), Value::List(other)) => x == other,
+            (Value::Set(x), Value::Set(other)) => {
+                x.len() == other.len() && x.iter().all(|value| other.contains(value))
+            }
+            (Value::Dict(x), Value::Dict(other)) => {
+                x.len() == other.len()
+                    && x.iter().all(|(key, value)| {
+                        other.iter().any(|(other_key, other_value)| {
+                            key == other_key && value == other_value
+                        })
+                    })
+            }
+            (Value::Optional(x), Value::Optional(other)) => x == other,
+            (Value::Builtin(x), Value::Builtin(other)) => x == other,
+            (Value::Module(x), Value::Module(other)) => Rc::ptr_eq(x, other),
+            (Value::Unit, Value::Unit) => true,
+            (Value::Native(x), Value::Native(other)) => Rc::ptr_eq(x, other),
+            (Value::Bytes(x), Value::Bytes(other)) => x == other,
+            (Value::Thunk(_, x), Value::Thunk(_, other)) => Rc::ptr_eq(x, other),
+            _ => false,
+        }
+    }
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "String",
+            Value::Char(_) => "Char",
+            Value::Int(_) => "Int",
+            Value::Float(_) => "Float",
+            Value::Bool(_) => "Bool",
+            Value::Tuple(_) => "Tuple",
+            Value::List(_) => "List",
+            Value::Set(_) => "Set",
+            Value::Dict(_) => "Dict",
+            Value::Optional(_) => "Optional",
+            Value::Lambda(..) => "Lambda",
+            Value::Builtin(_) => "Builtin",
+            Value::Module(_) => "Module",
+            Value::Unit => "Unit",
+            Value::Native(_) => "Native",
+            Value::Bytes(_) => "Bytes",
+            Value::Thunk(..) => "Thunk",
+        }
+    }
+}
+
+impl Value {
+    /// Orders two values of the same type. Ints and floats compare with each
+    /// other numerically and tuples compare element by element; any other
+    /// pairing, or a NaN, is a type error.
+    pub fn compare(&self, other: &Value) -> Result<Ordering, RuntimeError> {
+        let ordering = match (self, other) {
+            (Value::Int(x), Value::Int(other)) => Some(x.cmp(other)),
+            (Value::Float(x), Value::Float(other)) => x.partial_cmp(other),
+            (Value::Int(x), Value::Float(other)) => (*x as f64).partial_cmp(other),
+            (Value::Float(x), Value::Int(other)) => x.partial_cmp(&(*other as f64)),
+            (Value::String(x), Value::String(other)) => Some(x.cmp(other)),
+            (Value::Char(x), Value::Char(other)) => Some(x.cmp(other)),
+            (Value::Bool(x), Value::Bool(other)) => Some(x.cmp(other)),
+            (Value::Tuple(x), Value::Tuple(other)) => {
+                for (x, other) in x.iter().zip(other.iter()) {
+                    let ordering = x.compare(other)?;
+                    if ordering.is_ne() {
+                        return Ok(ordering);
+                    }
+                }
+                Some(x.len().cmp(&other.len()))
+            }
+            _ => None,
+        };
+
+        ordering.ok_or_else(|| self.incomparable(other))
+    }
+}
+
+impl Value {
+    /// Tests two values of the same type for equality. Ints and floats equal
+    /// each other numerically; any other pairing, or a lambda, is a type
+    /// error. Elements of collections compare structurally.
+    pub fn equals(&self, other: &Value) -> Result<bool, RuntimeError> {
+        match (self, other) {
+            (Value::Int(x), Value::Float(other)) => Ok(*x as f64 == *other),
+            (Value::Float(x), Value::Int(other)) => Ok(*x == *other as f64),
+            (Value::Lambda(..), _) | (_, Value::Lambda(..)) => Err(self.incomparable(other)),
+            _ if mem::discriminant(self) == mem::discriminant(other) => Ok(self == other),
+            _ => Err(self.incomparable(other)),
+        }
+    }
+
+    fn incomparable(&self, other: &Value) -> RuntimeError {
+        RuntimeError::TypeError(format!(
+            "Cannot compare {} with {}",
+            self.type_name(),
+            other.type_name()
+        ))
+    }
+}
+
+impl Value {
+    /// Whether the value can be a set member or dict key. Keys must have a
+    /// stable, total equality, so only ints, bools, strings, chars, unit,
+    /// bytes and tuples or optionals of those qualify.
+    pub fn is_hashable(&self) -> bool {
+        match self {
+            Value::Int(_)
+            | Value::Bool(_)
+            | Value::String(_)
+            | Value::Char(_)
+            | Value::Unit
+            | Value::Bytes(_) => true,
+            Value::Tuple(elements) => elements.iter().all(Value::is_hashable),
+            Value::Optional(value) => value.as_deref().is_none_or(Value::is_hashable),
             _ => false,
         }
     }
+
+    pub fn check_hashable(&self) -> Result<(), RuntimeError> {
+        if self.is_hashable() {
+            Ok(())
+        } else {
+            Err(RuntimeError::TypeError(format!(
+                "Unhashable type: {}",
+                self.type_name()
+            )))
+        }
+    }
+}
+
+/// A hashable value, which can key a `HashMap`. Equality on hashable values
+/// is total, so a key is `Eq` even though values in general aren't.
+#[derive(Debug, PartialEq)]
+pub struct Key(Value);
+
+impl Eq for Key {}
+
+impl Key {
+    pub fn new(value: Value) -> Result<Key, RuntimeError> {
+        value.check_hashable()?;
+        Ok(Key(value))
+    }
+}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        fn hash_value<H: Hasher>(value: &Value, state: &mut H) {
+            mem::discriminant(value).hash(state);
+            match value {
+                Value::Int(x) => x.hash(state),
+                Value::Bool(x) => x.hash(state),
+                Value::String(x) => x.hash(state),
+                Value::Char(x) => x.hash(state),
+                Value::Bytes(x) => x.hash(state),
+                Value::Tuple(elements) => {
+                    elements.len().hash(state);
+                    for element in elements.iter() {
+                        hash_value(element, state);
+                    }
+                }
+                Value::Optional(Some(value)) => hash_value(value, state),
+                _ => {}
+            }
+        }
+        hash_value(&self.0, state);
+    }
+}
+
+/// How values read in messages and the REPL. Collections separate their
+/// elements with `, `, and dicts their keys and values with `: `. Strings
+/// and chars are always quoted, at the top level as well as inside
+/// collections, so `"1"` and `1` never look alike.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn join<'a>(
+            f: &mut fmt::Formatter<'_>,
+            values: impl IntoIterator<Item = &'a Value>,
+        ) -> fmt::Result {
+            for (i, value) in values.into_iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{value}")?;
+            }
+            Ok(())
+        }
+
+        match self {
+            Value::String(value) => write!(f, "{value:?}"),
+            Value::Char(value) => write!(f, "{value:?}"),
+            Value::Int(value) => write!(f, "{value}"),
+            Value::Float(value) => write!(f, "{value:?}"),
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::Tuple(values) => {
+                write!(f, "(")?;
+                join(f, values.iter())?;
+                if values.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Value::List(values) => {
+                write!(f, "[")?;
+                join(f, values)?;
+                write!(f, "]")
+            }
+            Value::Set(values) => {
+                write!(f, "{{")?;
+                join(f, values.iter())?;
+                write!(f, "}}")
+            }
+            Value::Dict(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{key}: {value}")?;
+                }
+                write!(f, "}}")
+            }
+            Value::Optional(Some(value)) => write!(f, "some({value})"),
+            Value::Optional(None) => write!(f, "none"),
+            Value::Lambda(..) | Value::Native(_) => write!(f, "<fn>"),
+            Value::Builtin(name) => write!(f, "<builtin {name}>"),
+            Value::Module(_) => write!(f, "<module>"),
+            Value::Unit => write!(f, "()"),
+            Value::Bytes(bytes) => write!(f, "b\"{}\"", bytes.escape_ascii()),
+            Value::Thunk(..) => write!(f, "<thunk>"),
+        }
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value.into())
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.into())
+    }
+}
+
+/// Returned when converting a `Value` into a Rust type it doesn't hold.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeMismatch {
+    pub expected: &'static str,
+    pub found: Value,
+}
+
+impl fmt::Display for TypeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Type error: expected {}, found {}",
+            self.expected,
+            self.found.type_name()
+        )
+    }
+}
+
+impl std::error::Error for TypeMismatch {}
+
+impl TryFrom<Value> for i32 {
+    type Error = TypeMismatch;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Int(x) => Ok(x),
+            found => Err(TypeMismatch {
+                expected: "Int",
+                found,
+            }),
+        }
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = TypeMismatch;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Float(x) => Ok(x),
+            found => Err(TypeMismatch {
+                expected: "Float",
+                found,
+            }),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = TypeMismatch;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Bool(x) => Ok(x),
+            found => Err(TypeMismatch {
+                expected: "Bool",
+                found,
+            }),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = TypeMismatch;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(x) => Ok(x.to_string()),
+            found => Err(TypeMismatch {
+                expected: "String",
+                found,
+            }),
+        }
+    }
+}
+
+/// A type named in an annotation, with any type parameters, e.g.
+/// `List[Int]`. Annotations are recorded but not yet checked.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Type {
+    pub name: String,
+    pub params: Vec<Type>,
+}
+
+/// The annotated types of a lambda's parameters, `None` where a parameter has
+/// no annotation, and of its return value.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Signature {
+    pub params: Vec<Option<Type>>,
+    pub ret: Option<Type>,
 }
 
 #[derive(Clone, Debug)]
 pub enum Expr {
     Binary(Box<Expr>, Token, Box<Expr>),
     Unary(Token, Box<Expr>),
+    /// Two or more comparisons in a row, `a < b <= c`, meaning `a < b && b <=
+    /// c` with each operand evaluated once, left to right.
+    Chain(Box<Expr>, Vec<(Token, Expr)>),
     Literal(Value),
     Group(Box<Expr>),
-    FunctionCall(Box<Expr>, Vec<Expr>),
+    /// Unwraps an optional, returning `none` from the enclosing function when
+    /// there is no value.
+    Propagate(Box<Expr>),
+    Tuple(Vec<Expr>),
+    List(Vec<Expr>),
+    Set(Vec<Expr>),
+    Dict(Vec<(Expr, Expr)>),
+    /// A call, with where its `!` appears in the source.
+    FunctionCall(Box<Expr>, Vec<Expr>, Location),
     Get(Box<Expr>, String),
-    Declaration(Box<Expr>, Option<Box<Expr>>),
-    Assignment(Box<Expr>, Box<Expr>),
+    /// Declares a variable, with an optional type annotation and initial
+    /// value.
+    Declaration(Box<Expr>, Option<Type>, Option<Box<Expr>>),
+    /// An assignment, with where its `=` appears in the source.
+    Assignment(Box<Expr>, Box<Expr>, Location),
     Block(Vec<Expr>),
-    While(Box<Expr>, Box<Expr>),
+    /// A while loop, with an optional branch that runs when the condition
+    /// becomes false but not when the loop is left with `break`.
+    While(Box<Expr>, Box<Expr>, Option<Box<Expr>>),
+    /// `[body for pattern in iterable if guard]`, with the guard optional.
+    Comprehension(Box<Expr>, Box<Expr>, Box<Expr>, Option<Box<Expr>>),
+    /// A while loop evaluating to a list of the value of each pass through
+    /// the body, the last statement's value for a block.
+    Collect(Box<Expr>, Box<Expr>),
+    Repeat(Box<Expr>, Box<Expr>),
+    Loop(Box<Expr>),
+    Return(Box<Expr>),
+    /// Leaves the innermost loop, or the enclosing loop with the label.
+    Break(Option<String>),
+    /// Skips to the next iteration of the innermost loop, or of the
+    /// enclosing loop with the label.
+    Continue(Option<String>),
+    /// A loop that `break` and `continue` in nested loops can name.
+    Labeled(String, Box<Expr>),
+    /// Runs the body, and if it throws runs the handler with the thrown value
+    /// bound to the name.
+    Try(Box<Expr>, String, Box<Expr>),
+    /// Evaluates another file and defines its top-level bindings in the
+    /// global scope.
+    Import(String),
+    /// Evaluates to the module holding another file's top-level bindings.
+    Module(String),
     If(Box<Expr>, Box<Expr>, Option<Box<Expr>>),
+    /// Continues when the condition holds, and otherwise runs the else
+    /// branch, which must leave the enclosing function or loop.
+    Guard(Box<Expr>, Box<Expr>),
+    Match(Box<Expr>, Vec<(Expr, Option<Expr>, Expr)>),
+    /// Runs the body of the first case whose value equals the scrutinee, or
+    /// the default when none does. Cases never fall through.
+    Switch(Box<Expr>, Vec<(Expr, Expr)>, Option<Box<Expr>>),
     BuiltinFunction(Token),
-    Lambda(Vec<Expr>, Vec<Expr>),
-    Identifier(String),
+    Lambda(Rc<[Expr]>, Rc<[Expr]>, Signature),
+    /// A lambda whose closure holds copies of the named variables, taken when
+    /// the lambda is created, rather than references to them.
+    CaptureByValue(Vec<String>, Box<Expr>),
+    /// A name, with where it appears in the source.
+    Identifier(String, Location),
+}
+
+impl Expr {
+    /// The location of the first token of the expression that has one.
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            Expr::Identifier(_, location) => Some(*location),
+            Expr::Binary(lhs, op, _) => lhs.location().or(Some(op.location)),
+            Expr::Chain(first, comparisons) => first.location().or(Some(comparisons[0].0.location)),
+            Expr::Unary(op, _) => Some(op.location),
+            Expr::FunctionCall(callee, _, location) | Expr::Assignment(callee, _, location) => {
+                callee.location().or(Some(*location))
+            }
+            Expr::BuiltinFunction(token) => Some(token.location),
+            expr => expr.children().into_iter().find_map(Expr::location),
+        }
+    }
+
+    /// The expressions directly inside this one, in source order.
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Literal(_)
+            | Expr::Break(_)
+            | Expr::Continue(_)
+            | Expr::Import(_)
+            | Expr::Module(_)
+            | Expr::BuiltinFunction(_)
+            | Expr::Identifier(..) => vec![],
+            Expr::Unary(_, e)
+            | Expr::Group(e)
+            | Expr::Propagate(e)
+            | Expr::Get(e, _)
+            | Expr::Loop(e)
+            | Expr::Return(e)
+            | Expr::Labeled(_, e)
+            | Expr::CaptureByValue(_, e) => vec![e],
+            Expr::Binary(lhs, _, rhs) | Expr::Assignment(lhs, rhs, _) => vec![lhs, rhs],
+            Expr::Chain(first, comparisons) => std::iter::once(first.as_ref())
+                .chain(comparisons.iter().map(|(_, operand)| operand))
+                .collect(),
+            Expr::Repeat(body, cond) => vec![body, cond],
+            Expr::Collect(cond, body) => vec![cond, body],
+            Expr::Comprehension(body, pattern, iterable, guard) => {
+                [body.as_ref(), pattern.as_ref(), iterable.as_ref()]
+                    .into_iter()
+                    .chain(guard.as_deref())
+                    .collect()
+            }
+            Expr::Try(body, _, handler) => vec![body, handler],
+            Expr::Guard(cond, else_branch) => vec![cond, else_branch],
+            Expr::Tuple(elements) | Expr::List(elements) | Expr::Set(elements) => {
+                elements.iter().collect()
+            }
+            Expr::Block(exprs) => exprs.iter().collect(),
+            Expr::Dict(entries) => entries
+                .iter()
+                .flat_map(|(key, value)| [key, value])
+                .collect(),
+            Expr::FunctionCall(callee, args, _) => {
+                std::iter::once(callee.as_ref()).chain(args).collect()
+            }
+            Expr::Declaration(lhs, _, init) => std::iter::once(lhs.as_ref())
+                .chain(init.as_deref())
+                .collect(),
+            Expr::While(cond, body, else_branch) | Expr::If(cond, body, else_branch) => {
+                [cond.as_ref(), body.as_ref()]
+                    .into_iter()
+                    .chain(else_branch.as_deref())
+                    .collect()
+            }
+            Expr::Match(scrutinee, arms) => std::iter::once(scrutinee.as_ref())
+                .chain(arms.iter().flat_map(|(pattern, guard, body)| {
+                    std::iter::once(pattern).chain(guard).chain([body])
+                }))
+                .collect(),
+            Expr::Switch(scrutinee, cases, default) => std::iter::once(scrutinee.as_ref())
+                .chain(cases.iter().flat_map(|(case, body)| [case, body]))
+                .chain(default.as_deref())
+                .collect(),
+            Expr::Lambda(params, body, _) => params.iter().chain(body.iter()).collect(),
+        }
+    }
+}
+
+/// Walks an `Expr` tree, for tooling such as linters and analyzers. There is
+/// a method per variant, each of which by default visits the node's
+/// subexpressions in source order, so a visitor only overrides the nodes it
+/// is interested in.
+pub trait Visitor {
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_exprs(&mut self, exprs: &[Expr]) {
+        for expr in exprs {
+            self.visit_expr(expr);
+        }
+    }
+
+    fn visit_binary(&mut self, lhs: &Expr, _op: &Token, rhs: &Expr) {
+        self.visit_expr(lhs);
+        self.visit_expr(rhs);
+    }
+
+    fn visit_unary(&mut self, _op: &Token, operand: &Expr) {
+        self.visit_expr(operand);
+    }
+
+    fn visit_chain(&mut self, first: &Expr, comparisons: &[(Token, Expr)]) {
+        self.visit_expr(first);
+        for (_, operand) in comparisons {
+            self.visit_expr(operand);
+        }
+    }
+
+    fn visit_literal(&mut self, _value: &Value) {}
+
+    fn visit_group(&mut self, expr: &Expr) {
+        self.visit_expr(expr);
+    }
+
+    fn visit_propagate(&mut self, expr: &Expr) {
+        self.visit_expr(expr);
+    }
+
+    fn visit_tuple(&mut self, elements: &[Expr]) {
+        self.visit_exprs(elements);
+    }
+
+    fn visit_list(&mut self, elements: &[Expr]) {
+        self.visit_exprs(elements);
+    }
+
+    fn visit_set(&mut self, elements: &[Expr]) {
+        self.visit_exprs(elements);
+    }
+
+    fn visit_dict(&mut self, entries: &[(Expr, Expr)]) {
+        for (key, value) in entries {
+            self.visit_expr(key);
+            self.visit_expr(value);
+        }
+    }
+
+    fn visit_call(&mut self, callee: &Expr, args: &[Expr]) {
+        self.visit_expr(callee);
+        self.visit_exprs(args);
+    }
+
+    fn visit_get(&mut self, obj: &Expr, _name: &str) {
+        self.visit_expr(obj);
+    }
+
+    fn visit_declaration(&mut self, lhs: &Expr, _annotation: Option<&Type>, init: Option<&Expr>) {
+        self.visit_expr(lhs);
+        if let Some(init) = init {
+            self.visit_expr(init);
+        }
+    }
+
+    fn visit_assignment(&mut self, lhs: &Expr, value: &Expr) {
+        self.visit_expr(lhs);
+        self.visit_expr(value);
+    }
+
+    fn visit_block(&mut self, exprs: &[Expr]) {
+        self.visit_exprs(exprs);
+    }
+
+    fn visit_while(&mut self, cond: &Expr, body: &Expr, else_branch: Option<&Expr>) {
+        self.visit_expr(cond);
+        self.visit_expr(body);
+        if let Some(else_branch) = else_branch {
+            self.visit_expr(else_branch);
+        }
+    }
+
+    fn visit_comprehension(
+        &mut self,
+        body: &Expr,
+        pattern: &Expr,
+        iterable: &Expr,
+        guard: Option<&Expr>,
+    ) {
+        self.visit_expr(body);
+        self.visit_expr(pattern);
+        self.visit_expr(iterable);
+        if let Some(guard) = guard {
+            self.visit_expr(guard);
+        }
+    }
+
+    fn visit_collect(&mut self, cond: &Expr, body: &Expr) {
+        self.visit_expr(cond);
+        self.visit_expr(body);
+    }
+
+    fn visit_repeat(&mut self, body: &Expr, cond: &Expr) {
+        self.visit_expr(body);
+        self.visit_expr(cond);
+    }
+
+    fn visit_loop(&mut self, body: &Expr) {
+        self.visit_expr(body);
+    }
+
+    fn visit_return(&mut self, expr: &Expr) {
+        self.visit_expr(expr);
+    }
+
+    fn visit_break(&mut self, _label: Option<&str>) {}
+
+    fn visit_continue(&mut self, _label: Option<&str>) {}
+
+    fn visit_labeled(&mut self, _label: &str, expr: &Expr) {
+        self.visit_expr(expr);
+    }
+
+    fn visit_try(&mut self, body: &Expr, _name: &str, handler: &Expr) {
+        self.visit_expr(body);
+        self.visit_expr(handler);
+    }
+
+    fn visit_import(&mut self, _path: &str) {}
+
+    fn visit_module(&mut self, _path: &str) {}
+
+    fn visit_if(&mut self, cond: &Expr, true_branch: &Expr, false_branch: Option<&Expr>) {
+        self.visit_expr(cond);
+        self.visit_expr(true_branch);
+        if let Some(false_branch) = false_branch {
+            self.visit_expr(false_branch);
+        }
+    }
+
+    fn visit_guard(&mut self, cond: &Expr, else_branch: &Expr) {
+        self.visit_expr(cond);
+        self.visit_expr(else_branch);
+    }
+
+    fn visit_match(&mut self, scrutinee: &Expr, arms: &[(Expr, Option<Expr>, Expr)]) {
+        self.visit_expr(scrutinee);
+        for (pattern, guard, body) in arms {
+            self.visit_expr(pattern);
+            if let Some(guard) = guard {
+                self.visit_expr(guard);
+            }
+            self.visit_expr(body);
+        }
+    }
+
+    fn visit_switch(&mut self, scrutinee: &Expr, cases: &[(Expr, Expr)], default: Option<&Expr>) {
+        self.visit_expr(scrutinee);
+        for (case, body) in cases {
+            self.visit_expr(case);
+            self.visit_expr(body);
+        }
+        if let Some(default) = default {
+            self.visit_expr(default);
+        }
+    }
+
+    fn visit_builtin(&mut self, _token: &Token) {}
+
+    fn visit_lambda(&mut self, params: &[Expr], body: &[Expr], _signature: &Signature) {
+        self.visit_exprs(params);
+        self.visit_exprs(body);
+    }
+
+    fn visit_capture_by_value(&mut self, _captures: &[String], lambda: &Expr) {
+        self.visit_expr(lambda);
+    }
+
+    fn visit_identifier(&mut self, _name: &str, _location: Location) {}
+}
+
+/// Calls the `visitor` method for the variant of `expr`.
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Binary(lhs, op, rhs) => visitor.visit_binary(lhs, op, rhs),
+        Expr::Unary(op, operand) => visitor.visit_unary(op, operand),
+        Expr::Chain(first, comparisons) => visitor.visit_chain(first, comparisons),
+        Expr::Literal(value) => visitor.visit_literal(value),
+        Expr::Group(expr) => visitor.visit_group(expr),
+        Expr::Propagate(expr) => visitor.visit_propagate(expr),
+        Expr::Tuple(elements) => visitor.visit_tuple(elements),
+        Expr::List(elements) => visitor.visit_list(elements),
+        Expr::Set(elements) => visitor.visit_set(elements),
+        Expr::Dict(entries) => visitor.visit_dict(entries),
+        Expr::FunctionCall(callee, args, _) => visitor.visit_call(callee, args),
+        Expr::Get(obj, name) => visitor.visit_get(obj, name),
+        Expr::Declaration(lhs, annotation, init) => {
+            visitor.visit_declaration(lhs, annotation.as_ref(), init.as_deref())
+        }
+        Expr::Assignment(lhs, value, _) => visitor.visit_assignment(lhs, value),
+        Expr::Block(exprs) => visitor.visit_block(exprs),
+        Expr::While(cond, body, else_branch) => {
+            visitor.visit_while(cond, body, else_branch.as_deref())
+        }
+        Expr::Collect(cond, body) => visitor.visit_collect(cond, body),
+        Expr::Comprehension(body, pattern, iterable, guard) => {
+            visitor.visit_comprehension(body, pattern, iterable, guard.as_deref())
+        }
+        Expr::Repeat(body, cond) => visitor.visit_repeat(body, cond),
+        Expr::Loop(body) => visitor.visit_loop(body),
+        Expr::Return(expr) => visitor.visit_return(expr),
+        Expr::Break(label) => visitor.visit_break(label.as_deref()),
+        Expr::Continue(label) => visitor.visit_continue(label.as_deref()),
+        Expr::Labeled(label, expr) => visitor.visit_labeled(label, expr),
+        Expr::Try(body, name, handler) => visitor.visit_try(body, name, handler),
+        Expr::Import(path) => visitor.visit_import(path),
+        Expr::Module(path) => visitor.visit_module(path),
+        Expr::If(cond, true_branch, false_branch) => {
+            visitor.visit_if(cond, true_branch, false_branch.as_deref())
+        }
+        Expr::Guard(cond, else_branch) => visitor.visit_guard(cond, else_branch),
+        Expr::Match(scrutinee, arms) => visitor.visit_match(scrutinee, arms),
+        Expr::Switch(scrutinee, cases, default) => {
+            visitor.visit_switch(scrutinee, cases, default.as_deref())
+        }
+        Expr::BuiltinFunction(token) => visitor.visit_builtin(token),
+        Expr::Lambda(params, body, signature) => visitor.visit_lambda(params, body, signature),
+        Expr::CaptureByValue(captures, lambda) => visitor.visit_capture_by_value(captures, lambda),
+        Expr::Identifier(name, location) => visitor.visit_identifier(name, *location),
+    }
 }
@@ -1,21 +1,76 @@
+use std::fmt;
+
+/// The longest string or number literal scanned by default, in bytes.
+pub const DEFAULT_MAX_LITERAL_LENGTH: usize = 1 << 20;
+
 pub struct Scanner {
-    stream: String,
+    stream: Vec<char>,
     curr_loc: Location,
     start_loc: Location,
     tokens: Vec<Token>,
     open_block: Option<Location>,
     block_levels: Vec<usize>,
     line_start: Option<usize>,
+    max_literal_length: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScanError {
+    UnterminatedString {
+        line: usize,
+    },
+    LiteralTooLong {
+        line: usize,
+        max: usize,
+    },
+    /// A run of operator characters that isn't an operator, such as `&` or
+    /// `**`.
+    UnknownOperator {
+        line: usize,
+        operator: String,
+    },
+    /// An integer literal too big for an int.
+    IntTooLarge {
+        line: usize,
+        literal: String,
+    },
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanError::UnterminatedString { line } => {
+                write!(f, "Unterminated string starting on line {line}")
+            }
+            ScanError::LiteralTooLong { line, max } => {
+                write!(
+                    f,
+                    "Literal starting on line {line} is longer than {max} bytes"
+                )
+            }
+            ScanError::UnknownOperator { line, operator } => {
+                write!(f, "Unknown operator {operator} on line {line}")
+            }
+            ScanError::IntTooLarge { line, literal } => {
+                write!(f, "Integer {literal} on line {line} is too large")
+            }
+        }
+    }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+impl std::error::Error for ScanError {}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum TokenType {
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
+    Question,
     Plus,
     Semicolon,
     Colon,
@@ -31,9 +86,7 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
-    And,
     AndAnd,
-    Or,
     OrOr,
     Slash,
     SlashSlash,
@@ -42,11 +95,17 @@ pub enum TokenType {
     Minus,
     ThinArrow,
     Pipeline,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
 
     // Literals.
     Identifier(String),
     String(String),
+    Char(char),
     Int(i32),
+    Float(f64),
 
     // Keywords.
     Then,
@@ -54,23 +113,39 @@ pub enum TokenType {
     False,
     Fn,
     For,
+    In,
     While,
+    Collect,
+    Repeat,
+    Until,
+    Loop,
+    Break,
+    Continue,
+    Try,
+    Catch,
+    Import,
     If,
+    Guard,
+    Match,
+    Switch,
+    Case,
+    Default,
+    None,
     Print,
+    Assert,
+    AssertEq,
     Return,
     True,
 
     EndOfFile,
 
-    Comment(String),
-
     BeginBlock,
     EndBlock,
 }
 
 #[derive(Clone, Copy, Debug)]
 pub struct Location {
-    line: usize,
+    pub line: usize,
     pub col: usize,
     index: usize,
 }
@@ -83,8 +158,14 @@ pub struct Token {
 
 impl Scanner {
     pub fn new() -> Self {
+        Scanner::with_max_literal_length(DEFAULT_MAX_LITERAL_LENGTH)
+    }
+
+    /// A scanner that rejects string and number literals longer than
+    /// `max_literal_length` bytes.
+    pub fn with_max_literal_length(max_literal_length: usize) -> Self {
         Scanner {
-            stream: String::default(),
+            stream: Vec::default(),
             curr_loc: Location {
                 col: 0,
                 index: 0,
@@ -99,30 +180,22 @@ impl Scanner {
             open_block: None,
             block_levels: Vec::default(),
             line_start: None,
+            max_literal_length,
         }
     }
 
     pub fn emit_token(&mut self, token_type: TokenType) {
+        // Token lines are 1-based, as an editor shows them
         self.tokens.push(Token {
             token_type,
-            location: self.start_loc,
+            location: Location {
+                line: self.start_loc.line + 1,
+                ..self.start_loc
+            },
         });
         self.start_loc = self.curr_loc;
     }
 
-    pub fn check(&mut self, lexeme: char) -> bool {
-        if self.curr_loc.index >= self.stream.len() {
-            false
-        } else {
-            let c = self.stream.chars().nth(self.curr_loc.index);
-            if let Some(c) = c {
-                self.next();
-                return c == lexeme;
-            }
-            false
-        }
-    }
-
     fn emit_open_block(&mut self) {
         if let Some(curr_block_col) = self.block_levels.last() {
             if curr_block_col >= &self.curr_loc.col {
@@ -152,33 +225,96 @@ impl Scanner {
         }
     }
 
-    fn emit_string(&mut self, boundary: char) {
+    /// Appends `c` to the literal being scanned, unless that would make it
+    /// too long.
+    fn push_literal(&self, value: &mut String, c: char, line: usize) -> Result<(), ScanError> {
+        if value.len() + c.len_utf8() > self.max_literal_length {
+            return Err(ScanError::LiteralTooLong {
+                line,
+                max: self.max_literal_length,
+            });
+        }
+        value.push(c);
+        Ok(())
+    }
+
+    fn emit_string(&mut self, boundary: char) -> Result<(), ScanError> {
+        let line = self.curr_loc.line + 1;
         let mut value = String::default();
         loop {
             match self.next() {
-                None => todo!("Handle error here"),
+                None => return Err(ScanError::UnterminatedString { line }),
                 Some(c) => {
                     if c == boundary {
-                        self.emit_token(TokenType::String(value));
-                        return;
+                        // A single character in single quotes is a char, any
+                        // other single-quoted text is a string.
+                        let mut chars = value.chars();
+                        match (boundary, chars.next(), chars.next()) {
+                            ('\'', Some(c), None) => self.emit_token(TokenType::Char(c)),
+                            _ => self.emit_token(TokenType::String(value)),
+                        }
+                        return Ok(());
                     }
-                    value.push(c);
+                    self.push_literal(&mut value, c, line)?;
                 }
             }
         }
     }
 
-    fn emit_int(&mut self, first: char) {
-        let mut value = String::from(first);
+    /// Reads a `"""` string, which runs verbatim, newlines included, up to the
+    /// next `"""`.
+    fn emit_triple_quoted_string(&mut self) -> Result<(), ScanError> {
+        let line = self.curr_loc.line + 1;
+        let mut value = String::default();
+        loop {
+            match self.next() {
+                None => return Err(ScanError::UnterminatedString { line }),
+                Some('"') if self.peek() == Some('"') && self.peek_next() == Some('"') => {
+                    self.next();
+                    self.next();
+                    self.emit_token(TokenType::String(value));
+                    return Ok(());
+                }
+                Some(c) => self.push_literal(&mut value, c, line)?,
+            }
+        }
+    }
+
+    fn scan_digits(&mut self, value: &mut String) -> Result<(), ScanError> {
+        let line = self.curr_loc.line + 1;
         while let Some(c) = self.peek() {
             if !c.is_ascii_digit() {
                 break;
             }
             self.next();
-            value.push(c);
+            self.push_literal(value, c, line)?;
+        }
+        Ok(())
+    }
+
+    fn emit_number(&mut self, first: char) -> Result<(), ScanError> {
+        let line = self.curr_loc.line + 1;
+        let mut value = String::from(first);
+        self.scan_digits(&mut value)?;
+        if self.peek() == Some('.') && self.peek_next().is_some_and(|c| c.is_ascii_digit()) {
+            self.next();
+            value.push('.');
+            self.scan_digits(&mut value)?;
+            let value = value.parse().expect("Failed to parse float");
+            self.emit_token(TokenType::Float(value));
+            return Ok(());
         }
-        let value = value.parse().expect("Failed to parse int");
-        self.emit_token(TokenType::Int(value))
+        let value = match value.parse() {
+            Ok(value) => value,
+            Err(_) => {
+                return Err(ScanError::IntTooLarge {
+                    line,
+                    literal: value,
+                })
+            }
+        };
+        self.emit_token(TokenType::Int(value));
+        Ok(())
     }
 
     fn identifier_symbol(c: char) -> bool {
@@ -226,18 +362,35 @@ impl Scanner {
     }
 
     pub fn scan(&mut self, line: String) -> Vec<Token> {
+        self.try_scan(line).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_scan(&mut self, line: String) -> Result<Vec<Token>, ScanError> {
+        *self = Scanner::with_max_literal_length(self.max_literal_length);
+        // Splitting on lines drops the carriage return of CRLF line endings,
+        // so columns, and so blocks, come out the same as for LF files.
         self.stream = line
             .lines()
-            .filter(|line| !line.trim().is_empty())
             .collect::<Vec<_>>()
-            .join("\n");
+            .join("\n")
+            .chars()
+            .collect();
         while let Some(c) = self.next() {
+            // Comments run to the end of the line and, like whitespace, take
+            // no part in the layout of blocks.
+            if c == '#' {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.next();
+                }
+                continue;
+            }
+
             if !matches!(c, '\n' | ' ' | '\t' | '\r') {
                 if let Some(opening_loc) = self.open_block {
                     if self.curr_loc.line == opening_loc.line {
                         self.open_block = None;
                     } else {
-                        if self.line_start.map_or(false, |x| x < self.curr_loc.col) {
+                        if self.line_start.is_some_and(|x| x < self.curr_loc.col) {
                             self.emit_open_block();
                         } else {
                             self.open_block = None;
@@ -263,10 +416,13 @@ impl Scanner {
                 //One character tokens
                 '(' => self.emit_token(TokenType::LeftParen),
                 ')' => self.emit_token(TokenType::RightParen),
-                '{' => self.emit_token(TokenType::RightBrace),
-                '}' => self.emit_token(TokenType::LeftBrace),
+                '{' => self.emit_token(TokenType::LeftBrace),
+                '}' => self.emit_token(TokenType::RightBrace),
+                '[' => self.emit_token(TokenType::LeftBracket),
+                ']' => self.emit_token(TokenType::RightBracket),
                 ',' => self.emit_token(TokenType::Comma),
                 '.' => self.emit_token(TokenType::Dot),
+                '?' => self.emit_token(TokenType::Question),
                 ';' => self.emit_token(TokenType::Semicolon),
                 ':' => {
                     self.open_block = Some(self.curr_loc);
@@ -274,11 +430,14 @@ impl Scanner {
                 }
                 //ignored characters
                 '\n' | ' ' | '\t' | '\r' => {}
-                '"' => self.emit_string('"'),
-                '\'' => self.emit_string('\''),
-                c if c.is_ascii_digit() => {
-                    self.emit_int(c);
+                '"' if self.peek() == Some('"') && self.peek_next() == Some('"') => {
+                    self.next();
+                    self.next();
+                    self.emit_triple_quoted_string()?
                 }
+                '"' => self.emit_string('"')?,
+                '\'' => self.emit_string('\'')?,
+                c if c.is_ascii_digit() => self.emit_number(c)?,
                 c => {
                     let id = self.scan_identifier(c);
                     match id.as_str() {
@@ -294,9 +453,7 @@ impl Scanner {
                         "<" => self.emit_token(TokenType::Less),
                         ">" => self.emit_token(TokenType::Greater),
                         ">=" => self.emit_token(TokenType::GreaterEqual),
-                        "&" => self.emit_token(TokenType::And),
                         "&&" => self.emit_token(TokenType::AndAnd),
-                        "|" => self.emit_token(TokenType::Or),
                         "||" => self.emit_token(TokenType::OrOr),
                         "/" => self.emit_token(TokenType::Slash),
                         "//" => self.emit_token(TokenType::SlashSlash),
@@ -306,6 +463,10 @@ impl Scanner {
                         "+" => self.emit_token(TokenType::Plus),
                         "*" => self.emit_token(TokenType::Star),
                         "|>" => self.emit_token(TokenType::Pipeline),
+                        "+=" => self.emit_token(TokenType::PlusEqual),
+                        "-=" => self.emit_token(TokenType::MinusEqual),
+                        "*=" => self.emit_token(TokenType::StarEqual),
+                        "/=" => self.emit_token(TokenType::SlashEqual),
                         "->" => {
                             self.open_block = Some(self.curr_loc);
                             self.emit_token(TokenType::ThinArrow)
@@ -314,34 +475,64 @@ impl Scanner {
                         "true" => self.emit_token(TokenType::True),
                         "fn" => self.emit_token(TokenType::Fn),
                         "for" => self.emit_token(TokenType::For),
+                        "in" => self.emit_token(TokenType::In),
                         "while" => self.emit_token(TokenType::While),
+                        "collect" => self.emit_token(TokenType::Collect),
+                        "repeat" => self.emit_token(TokenType::Repeat),
+                        "until" => self.emit_token(TokenType::Until),
+                        "loop" => self.emit_token(TokenType::Loop),
+                        "break" => self.emit_token(TokenType::Break),
+                        "continue" => self.emit_token(TokenType::Continue),
+                        "try" => self.emit_token(TokenType::Try),
+                        "catch" => self.emit_token(TokenType::Catch),
+                        "import" => self.emit_token(TokenType::Import),
                         "return" => self.emit_token(TokenType::Return),
                         "print" => self.emit_token(TokenType::Print),
+                        "assert" => self.emit_token(TokenType::Assert),
+                        "assert_eq" => self.emit_token(TokenType::AssertEq),
                         "if" => self.emit_token(TokenType::If),
+                        "guard" => self.emit_token(TokenType::Guard),
+                        "match" => self.emit_token(TokenType::Match),
+                        "switch" => self.emit_token(TokenType::Switch),
+                        "case" => self.emit_token(TokenType::Case),
+                        "default" => self.emit_token(TokenType::Default),
+                        "none" => self.emit_token(TokenType::None),
                         "then" => self.emit_token(TokenType::Then),
                         "else" => self.emit_token(TokenType::Else),
+                        // Raw strings copy everything up to the closing quote,
+                        // backslashes included, and so cannot contain a quote.
+                        "r" if self.peek() == Some('"') => {
+                            self.next();
+                            self.emit_string('"')?
+                        }
+                        _ if Scanner::identifier_symbol(c) => {
+                            return Err(ScanError::UnknownOperator {
+                                line: self.curr_loc.line + 1,
+                                operator: id,
+                            })
+                        }
                         _ => self.emit_token(TokenType::Identifier(id)),
                     }
                 }
             }
         }
         self.emit_end_of_file();
-        self.tokens.drain(..).collect()
+        Ok(self.tokens.drain(..).collect())
     }
 
     fn peek(&mut self) -> Option<char> {
-        if self.curr_loc.index >= self.stream.len() {
-            None
-        } else {
-            self.stream.chars().nth(self.curr_loc.index)
-        }
+        self.stream.get(self.curr_loc.index).copied()
+    }
+
+    fn peek_next(&mut self) -> Option<char> {
+        self.stream.get(self.curr_loc.index + 1).copied()
     }
 
     fn next(&mut self) -> Option<char> {
         if self.curr_loc.index >= self.stream.len() {
             None
         } else {
-            let c = self.stream.chars().nth(self.curr_loc.index);
+            let c = self.stream.get(self.curr_loc.index).copied();
             match c {
                 Some('\n') => {
                     self.curr_loc.line += 1;